@@ -0,0 +1,14 @@
+-- api tokens made with the admin api, config tokens never land here
+CREATE TABLE tokens (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    -- sha256 of the secret part, in hex
+    hash TEXT NOT NULL,
+    -- json lists, everything is allowed when null
+    channels TEXT,
+    ops TEXT,
+    created_at INTEGER NOT NULL,
+    last_used INTEGER,
+    expires_at INTEGER,
+    revoked_at INTEGER
+);
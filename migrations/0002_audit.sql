@@ -0,0 +1,34 @@
+-- every abzar request, rows are only ever added
+CREATE TABLE audit (
+    id INTEGER PRIMARY KEY,
+    at INTEGER NOT NULL,
+    endpoint TEXT NOT NULL,
+    -- unknown when the request was refused before the body was read
+    channel TEXT,
+    -- token:<name>, hmac:<channel> or pass
+    identity TEXT,
+    ip TEXT,
+    -- sha256 of the text in hex and its first few characters
+    text_hash TEXT,
+    text_preview TEXT,
+    file_name TEXT,
+    file_mime TEXT,
+    file_size INTEGER,
+    message_id INTEGER,
+    status INTEGER NOT NULL,
+    -- the error code when the request failed
+    error TEXT
+);
+
+CREATE INDEX audit_at ON audit (at);
+CREATE INDEX audit_channel ON audit (channel, at);
+
+CREATE TRIGGER audit_no_update BEFORE UPDATE ON audit
+BEGIN
+    SELECT RAISE(ABORT, 'the audit log is append only');
+END;
+
+CREATE TRIGGER audit_no_delete BEFORE DELETE ON audit
+BEGIN
+    SELECT RAISE(ABORT, 'the audit log is append only');
+END;
@@ -0,0 +1,2 @@
+-- json rate and quota limits of a token, none when null
+ALTER TABLE tokens ADD COLUMN limits TEXT;
@@ -1,101 +1,71 @@
-use crate::models::Horp;
+use crate::audit::{self, Audit};
+use crate::auth::{self, Auth, Op};
+use crate::delivery::{self, Document, Message, ParseMode, Severity};
+use crate::delivery::{Source, Upload};
+use crate::models::{AppErr, Horp, Jorp};
 use crate::{config::Config, docs::UpdatePaths};
 
-use actix_multipart::form::tempfile::TempFile;
 use actix_multipart::form::{MultipartForm, text::Text};
+use actix_multipart::{Field, Multipart};
+use actix_web::dev::HttpServiceFactory;
+use actix_web::middleware::from_fn;
 use actix_web::{HttpResponse, Scope, post, web::Json};
+use futures_util::StreamExt;
+use std::collections::HashMap;
 
 #[derive(utoipa::OpenApi)]
 #[openapi(
     tags((name = "api::abzar")),
-    paths(r_send, r_send_file, r_send_mp),
+    paths(r_send, r_send_file, r_send_mp, r_send_local, r_edit, r_delete),
     servers((url = "/abzar")),
     modifiers(&UpdatePaths)
 )]
 pub struct ApiDoc;
 
-#[derive(Debug, serde::Deserialize, utoipa::ToSchema, Clone, Copy)]
-enum ParseMode {
-    Markdown,
-    MarkdownV2,
-    Html,
-}
-
-impl ParseMode {
-    fn as_str(&self) -> &'static str {
-        match self {
-            Self::MarkdownV2 => "MarkdownV2",
-            Self::Markdown => "Markdown",
-            Self::Html => "HTML",
-        }
-    }
+#[derive(Debug, serde::Serialize, utoipa::ToSchema)]
+struct AbzarSent {
+    /// id of the telegram message, for editing or deleting it later
+    message_id: Option<i64>,
 }
 
 #[derive(Debug, serde::Deserialize, utoipa::ToSchema)]
 struct AbzarSendBody {
     channel: String,
-    pass: String,
+    /// legacy auth, use a bearer token instead
+    pass: Option<String>,
     text: String,
     parse_mode: Option<ParseMode>,
-}
-
-#[derive(serde::Serialize)]
-struct LinkPreviewOptions {
-    is_disabled: bool,
-    prefer_small_media: bool,
-}
-
-impl Default for LinkPreviewOptions {
-    fn default() -> Self {
-        Self { is_disabled: false, prefer_small_media: true }
-    }
-}
-
-#[derive(serde::Serialize)]
-struct SendMessageBody<'a> {
-    chat_id: &'a str,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    message_thread_id: Option<&'a str>,
-    text: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    parse_mode: Option<&'static str>,
-    link_preview_options: LinkPreviewOptions,
+    severity: Option<Severity>,
 }
 
 #[utoipa::path(
     post,
     request_body = AbzarSendBody,
-    responses((status = 200))
+    responses((status = 200, body = AbzarSent))
 )]
 /// Send
 #[post("/send/")]
-async fn r_send(body: Json<AbzarSendBody>) -> Horp {
+async fn r_send(
+    auth: Auth, audit: Audit, body: Json<AbzarSendBody>,
+) -> Jorp<AbzarSent> {
     let conf = Config::get();
-    let Some(ch) = conf.channels.get(&body.channel) else {
-        return crate::err!(NotFound, "no channel");
+    audit.request(&auth, &body.channel, Some(&body.text));
+    let pass = body.pass.as_deref();
+    let ch = auth::channel(&conf, &auth, &body.channel, pass, Op::Send).await?;
+    let bytes = Some(body.text.len() as u64);
+    auth::admit(&auth, &body.channel, ch, bytes)?;
+
+    let msg = Message {
+        text: &body.text,
+        parse_mode: body.parse_mode,
+        severity: body.severity,
+        document: None,
     };
+    let message_id = delivery::send(ch, msg).await?;
+    audit.message(message_id);
+    auth::used_bytes(&auth, &body.channel, body.text.len() as u64);
 
-    if ch.pass != body.pass {
-        return crate::err!(NotFound, "no channel");
-    }
-
-    let url = conf.send_message.clone();
-
-    let bd = SendMessageBody {
-        chat_id: &ch.chat,
-        message_thread_id: ch.thread.as_ref().map(|v| v.as_str()),
-        text: body.text.clone(),
-        parse_mode: body.parse_mode.map(|v| v.as_str()),
-        link_preview_options: Default::default(),
-    };
-
-    let r = conf.tc.post(url).json(&bd).send().await?;
-    if r.status() != 200 {
-        log::error!("[tel_err]: {:#?}", r.text().await);
-        return crate::err!(SendFailed, "sending message to telegram failed");
-    }
-
-    Ok(HttpResponse::Ok().finish())
+    Ok(Json(AbzarSent { message_id }))
 }
 
 // #[derive(Debug, serde::Deserialize, utoipa::ToSchema)]
@@ -105,19 +75,72 @@ async fn r_send(body: Json<AbzarSendBody>) -> Horp {
 //     text: String,
 // }
 
-#[derive(Debug, MultipartForm, utoipa::ToSchema)]
+/// text fields of a send-file form together can not be bigger than this
+const FIELDS_LIMIT: usize = 64 * 1024;
+
+#[derive(utoipa::ToSchema)]
+/// the file must be the last field, it is streamed straight to the
+/// channel as it arrives and never touches the disk
 pub struct AbzarSendFileBody {
+    channel: String,
+    /// legacy auth, use a bearer token instead
+    pass: Option<String>,
+    text: String,
+    parse_mode: Option<ParseMode>,
+    severity: Option<Severity>,
     #[schema(value_type = String, format = Binary)]
-    #[multipart(limit = "50MB")]
-    file: TempFile,
-    #[schema(value_type = String)]
-    channel: Text<String>,
-    #[schema(value_type = String)]
-    pass: Text<String>,
-    #[schema(value_type = String)]
-    text: Text<String>,
-    #[schema(value_type = Option<ParseMode>)]
-    parse_mode: Option<Text<ParseMode>>,
+    file: Field,
+}
+
+impl AbzarSendFileBody {
+    fn new(
+        mut fields: HashMap<String, String>, file: Field,
+    ) -> Result<Self, AppErr> {
+        let mut take = |name: &str| match fields.remove(name) {
+            Some(v) => Ok(v),
+            None => crate::err!(
+                BadRequest,
+                format!("{name} is missing, it must come before the file")
+            ),
+        };
+
+        Ok(Self {
+            channel: take("channel")?,
+            pass: take("pass").ok(),
+            text: take("text")?,
+            parse_mode: take("parse_mode").ok().map(enum_field).transpose()?,
+            severity: take("severity").ok().map(enum_field).transpose()?,
+            file,
+        })
+    }
+}
+
+fn enum_field<T: serde::de::DeserializeOwned>(
+    value: String,
+) -> Result<T, AppErr> {
+    match serde_json::from_value(serde_json::Value::String(value)) {
+        Ok(v) => Ok(v),
+        Err(e) => crate::err!(BadRequest, e.to_string()),
+    }
+}
+
+async fn text_field(
+    field: &mut Field, used: &mut usize,
+) -> Result<String, AppErr> {
+    let mut data = Vec::new();
+    while let Some(chunk) = field.next().await {
+        let chunk = chunk?;
+        *used += chunk.len();
+        if *used > FIELDS_LIMIT {
+            return crate::err!(BadRequest, "form fields are too big");
+        }
+        data.extend_from_slice(&chunk);
+    }
+
+    match String::from_utf8(data) {
+        Ok(v) => Ok(v),
+        Err(_) => crate::err!(BadRequest, "form fields must be utf-8"),
+    }
 }
 
 #[utoipa::path(
@@ -126,66 +149,118 @@ pub struct AbzarSendFileBody {
         content = AbzarSendFileBody,
         content_type = "multipart/form-data"
     ),
-    responses((status = 200))
+    responses((status = 200, body = AbzarSent))
 )]
 /// Send File
 #[post("/send-file/")]
-async fn r_send_file(form: MultipartForm<AbzarSendFileBody>) -> Horp {
-    if form.file.size >= 50_000_000 {
-        return crate::err!(FileTooBig, "max file size is 50MB");
-    }
-
+async fn r_send_file(
+    auth: Auth, audit: Audit, mut mp: Multipart,
+) -> Jorp<AbzarSent> {
     let conf = Config::get();
-    let Some(ch) = conf.channels.get(&form.channel.0) else {
-        return crate::err!(NotFound, "no channel");
-    };
-
-    if ch.pass != form.pass.0 {
-        return crate::err!(NotFound, "no channel");
-    }
-
-    let url = conf.send_document.clone();
 
-    let mut doc = reqwest::multipart::Part::file(form.file.file.path()).await?;
-    if let Some(fname) = form.file.file_name.clone() {
-        doc = doc.file_name(fname);
-    }
-    if let Some(mime) = &form.file.content_type {
-        doc = doc.mime_str(&mime.to_string())?;
-    }
-
-    let mut sf = reqwest::multipart::Form::new()
-        .part("document", doc)
-        .text("chat_id", ch.chat.clone())
-        .text("caption", form.text.0.clone());
+    let mut fields = HashMap::new();
+    let mut used = 0;
+    let form = loop {
+        let Some(field) = mp.next().await else {
+            return crate::err!(BadRequest, "file is missing");
+        };
+        let mut field = field?;
+        let name = field.name().unwrap_or_default().to_string();
+        if name == "file" {
+            break AbzarSendFileBody::new(fields, field)?;
+        }
+        let value = text_field(&mut field, &mut used).await?;
+        fields.insert(name, value);
+    };
 
-    if let Some(pm) = &form.parse_mode {
-        sf = sf.text("parse_mode", pm.as_str());
-    }
+    let AbzarSendFileBody {
+        channel,
+        pass,
+        text,
+        parse_mode,
+        severity,
+        file: mut field,
+    } = form;
+
+    audit.request(&auth, &channel, Some(&text));
+    let ch =
+        auth::channel(&conf, &auth, &channel, pass.as_deref(), Op::SendFile)
+            .await?;
+    auth::admit(&auth, &channel, ch, auth.length)?;
+
+    let name = field
+        .content_disposition()
+        .and_then(|v| v.get_filename())
+        .map(String::from);
+    let mime = field.content_type().map(|v| v.to_string());
+    let (tx, upload) = Upload::new();
+
+    let msg = Message {
+        text: &text,
+        parse_mode,
+        severity,
+        document: Some(Document {
+            name: name.as_deref(),
+            mime: mime.clone(),
+            source: Source::Upload(upload),
+        }),
+    };
 
-    if let Some(tid) = &ch.thread {
-        sf = sf.text("message_thread_id", tid.clone());
-    }
+    let max = ch.target.max_file_size(&conf);
+    let pump = async move {
+        let mut size = 0u64;
+        while let Some(chunk) = field.next().await {
+            let chunk = match chunk {
+                Ok(v) => v,
+                Err(e) => {
+                    let _ =
+                        tx.send(Err(std::io::Error::other("aborted"))).await;
+                    return Err(AppErr::from(e));
+                }
+            };
+
+            size += chunk.len() as u64;
+            if size >= max {
+                let _ = tx.send(Err(std::io::Error::other("too big"))).await;
+                return crate::err!(
+                    FileTooBig,
+                    format!("max file size is {max} bytes")
+                );
+            }
+
+            // the backend gave up on the upload and reports its own error
+            if tx.send(Ok(chunk)).await.is_err() {
+                break;
+            }
+        }
+        Ok(size)
+    };
 
-    let r = conf.tc.post(url).multipart(sf).send().await?;
-    if r.status() != 200 {
-        log::error!("[tel_err]: {:#?}", r.text().await);
-        return crate::err!(SendFailed, "sending file to telegram failed");
-    }
+    let (sent, pumped) = tokio::join!(delivery::send(ch, msg), pump);
+    let size = pumped?;
+    audit.file(name.as_deref(), mime.as_deref(), size);
+    let l = [("channel", channel.as_str())];
+    crate::metrics::add("iris_upload_bytes_total", &l, size as f64);
+    let message_id = sent?;
+    audit.message(message_id);
+    auth::used_bytes(&auth, &channel, text.len() as u64 + size);
 
-    Ok(HttpResponse::Ok().finish())
+    Ok(Json(AbzarSent { message_id }))
 }
 
 #[derive(Debug, MultipartForm, utoipa::ToSchema)]
 pub struct AbzarSendMpBody {
     #[schema(value_type = String)]
     channel: Text<String>,
-    #[schema(value_type = String)]
-    pass: Text<String>,
+    /// legacy auth, use a bearer token instead
+    #[schema(value_type = Option<String>)]
+    pass: Option<Text<String>>,
     #[schema(value_type = String)]
     text: Text<String>,
     #[schema(value_type = Option<ParseMode>)]
     parse_mode: Option<Text<ParseMode>>,
+    #[schema(value_type = Option<Severity>)]
+    severity: Option<Text<Severity>>,
 }
 
 #[utoipa::path(
@@ -194,43 +269,189 @@ pub struct AbzarSendMpBody {
         content = AbzarSendMpBody,
         content_type = "multipart/form-data"
     ),
-    responses((status = 200))
+    responses((status = 200, body = AbzarSent))
 )]
 /// Send Message Multipart
 #[post("/send-mp/")]
-async fn r_send_mp(form: MultipartForm<AbzarSendMpBody>) -> Horp {
+async fn r_send_mp(
+    auth: Auth, audit: Audit, form: MultipartForm<AbzarSendMpBody>,
+) -> Jorp<AbzarSent> {
     // if form.file.size >= 50_000_000 {
     //     return crate::err!(FileTooBig, "max file size is 50MB");
     // }
 
     let conf = Config::get();
-    let Some(ch) = conf.channels.get(&form.channel.0) else {
-        return crate::err!(NotFound, "no channel");
+    audit.request(&auth, &form.channel, Some(&form.text));
+    let pass = form.pass.as_ref().map(|v| v.as_str());
+    let ch = auth::channel(&conf, &auth, &form.channel, pass, Op::Send).await?;
+    auth::admit(&auth, &form.channel, ch, Some(form.text.len() as u64))?;
+
+    let msg = Message {
+        text: &form.text,
+        parse_mode: form.parse_mode.as_ref().map(|v| v.0),
+        severity: form.severity.as_ref().map(|v| v.0),
+        document: None,
     };
+    let message_id = delivery::send(ch, msg).await?;
+    audit.message(message_id);
+    auth::used_bytes(&auth, &form.channel, form.text.len() as u64);
 
-    if ch.pass != form.pass.0 {
-        return crate::err!(NotFound, "no channel");
-    }
+    Ok(Json(AbzarSent { message_id }))
+}
+
+#[derive(Debug, serde::Deserialize, utoipa::ToSchema)]
+struct AbzarSendLocalBody {
+    channel: String,
+    /// legacy auth, use a bearer token instead
+    pass: Option<String>,
+    /// path of the file, must be inside the configured `local_dir`
+    path: String,
+    text: String,
+    parse_mode: Option<ParseMode>,
+    severity: Option<Severity>,
+}
+
+#[utoipa::path(
+    post,
+    request_body = AbzarSendLocalBody,
+    responses((status = 200, body = AbzarSent))
+)]
+/// Send Local File
+///
+/// send a file that is already on the server, handy with a local bot api
+/// server where files up to 2GB are handed over as `file://` paths
+#[post("/send-local/")]
+async fn r_send_local(
+    auth: Auth, audit: Audit, body: Json<AbzarSendLocalBody>,
+) -> Jorp<AbzarSent> {
+    let conf = Config::get();
+    audit.request(&auth, &body.channel, Some(&body.text));
+    let Some(local_dir) = &conf.local_dir else {
+        return crate::err!(Unsupported, "local_dir is not configured");
+    };
 
-    let url = conf.send_message.clone();
+    let pass = body.pass.as_deref();
+    let ch =
+        auth::channel(&conf, &auth, &body.channel, pass, Op::SendFile).await?;
 
-    let bd = SendMessageBody {
-        chat_id: &ch.chat,
-        message_thread_id: ch.thread.as_ref().map(|v| v.as_str()),
-        text: form.text.clone(),
-        parse_mode: form.parse_mode.as_ref().map(|v| v.as_str()),
-        link_preview_options: Default::default(),
+    // canonicalize resolves `..` and symlinks before the prefix check
+    let Ok(path) = tokio::fs::canonicalize(&body.path).await else {
+        return crate::err!(NotFound, "no file");
     };
+    if !path.starts_with(local_dir) {
+        return crate::err!(Forbidden, "file is outside of local_dir");
+    }
 
-    let r = conf.tc.post(url).json(&bd).send().await?;
-    if r.status() != 200 {
-        log::error!("[tel_err mp]: {:#?}", r.text().await);
-        return crate::err!(SendFailed, "sending message to telegram failed");
+    let meta = tokio::fs::metadata(&path).await?;
+    if !meta.is_file() {
+        return crate::err!(NotFound, "no file");
+    }
+    let max = ch.target.max_file_size(&conf);
+    if meta.len() >= max {
+        return crate::err!(
+            FileTooBig,
+            format!("max file size is {max} bytes")
+        );
     }
 
+    let bytes = Some(body.text.len() as u64 + meta.len());
+    auth::admit(&auth, &body.channel, ch, bytes)?;
+
+    let name = path.file_name().and_then(|v| v.to_str());
+    audit.file(name, None, meta.len());
+
+    let msg = Message {
+        text: &body.text,
+        parse_mode: body.parse_mode,
+        severity: body.severity,
+        document: Some(Document {
+            name,
+            mime: None,
+            source: Source::Local(&path),
+        }),
+    };
+    let message_id = delivery::send(ch, msg).await?;
+    audit.message(message_id);
+    auth::used_bytes(&auth, &body.channel, body.text.len() as u64 + meta.len());
+
+    Ok(Json(AbzarSent { message_id }))
+}
+
+#[derive(Debug, serde::Deserialize, utoipa::ToSchema)]
+struct AbzarEditBody {
+    channel: String,
+    /// legacy auth, use a bearer token instead
+    pass: Option<String>,
+    message_id: i64,
+    text: String,
+    parse_mode: Option<ParseMode>,
+}
+
+#[utoipa::path(
+    post,
+    request_body = AbzarEditBody,
+    responses((status = 200))
+)]
+/// Edit
+///
+/// replace the text of a message, telegram channels only
+#[post("/edit/")]
+async fn r_edit(auth: Auth, audit: Audit, body: Json<AbzarEditBody>) -> Horp {
+    let conf = Config::get();
+    audit.request(&auth, &body.channel, Some(&body.text));
+    audit.message(Some(body.message_id));
+    let pass = body.pass.as_deref();
+    let ch = auth::channel(&conf, &auth, &body.channel, pass, Op::Edit).await?;
+    auth::admit(&auth, &body.channel, ch, Some(body.text.len() as u64))?;
+
+    delivery::edit(ch, body.message_id, &body.text, body.parse_mode).await?;
+    auth::used_bytes(&auth, &body.channel, body.text.len() as u64);
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Debug, serde::Deserialize, utoipa::ToSchema)]
+struct AbzarDeleteBody {
+    channel: String,
+    /// legacy auth, use a bearer token instead
+    pass: Option<String>,
+    message_id: i64,
+}
+
+#[utoipa::path(
+    post,
+    request_body = AbzarDeleteBody,
+    responses((status = 200))
+)]
+/// Delete
+///
+/// remove a message, telegram channels only
+#[post("/delete/")]
+async fn r_delete(
+    auth: Auth, audit: Audit, body: Json<AbzarDeleteBody>,
+) -> Horp {
+    let conf = Config::get();
+    audit.request(&auth, &body.channel, None);
+    audit.message(Some(body.message_id));
+    let pass = body.pass.as_deref();
+    let ch =
+        auth::channel(&conf, &auth, &body.channel, pass, Op::Delete).await?;
+    auth::admit(&auth, &body.channel, ch, None)?;
+
+    delivery::delete(ch, body.message_id).await?;
+
     Ok(HttpResponse::Ok().finish())
 }
 
-pub fn router() -> Scope {
-    Scope::new("/abzar").service(r_send).service(r_send_file).service(r_send_mp)
+pub fn router() -> impl HttpServiceFactory {
+    Scope::new("/abzar")
+        .wrap(from_fn(auth::verify_signature))
+        // outermost, so requests refused by the signature check are recorded
+        .wrap(from_fn(audit::record))
+        .service(r_send)
+        .service(r_send_file)
+        .service(r_send_mp)
+        .service(r_send_local)
+        .service(r_edit)
+        .service(r_delete)
 }
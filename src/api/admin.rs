@@ -0,0 +1,183 @@
+use crate::auth::{self, Auth, Op};
+use crate::limits::Limit;
+use crate::models::{ApiToken, AppErr, AppState, Horp, Jorp};
+use crate::models::{AuditEntry, AuditFilter};
+use crate::utils::sys_now;
+use crate::{config::Config, docs::UpdatePaths};
+
+use actix_web::web::{Data, Json, Path, Query};
+use actix_web::{HttpResponse, Scope, delete, get, post};
+use std::collections::HashSet;
+
+#[derive(utoipa::OpenApi)]
+#[openapi(
+    tags((name = "api::admin")),
+    paths(
+        r_tokens_list, r_tokens_create, r_tokens_rotate, r_tokens_revoke,
+        r_audit
+    ),
+    components(schemas(ApiToken, AuditEntry, Limit)),
+    servers((url = "/admin")),
+    modifiers(&UpdatePaths)
+)]
+pub struct ApiDoc;
+
+#[derive(Debug, serde::Serialize, utoipa::ToSchema)]
+struct AdminTokenIssued {
+    /// the full token, it is not stored and can not be shown again
+    token: String,
+    info: ApiToken,
+}
+
+/// when a token made now should expire, `0` means never
+fn expires_at(expires_in: Option<i64>) -> Result<Option<i64>, AppErr> {
+    match expires_in.unwrap_or(Config::TOKEN_LIFE) {
+        0 => Ok(None),
+        v if v < 0 => crate::err!(BadRequest, "expires_in can not be negative"),
+        v => Ok(Some(sys_now() + v)),
+    }
+}
+
+#[utoipa::path(get, responses((status = 200, body = Vec<ApiToken>)))]
+/// List Tokens
+///
+/// tokens made with the admin api, revoked ones included
+#[get("/tokens/")]
+async fn r_tokens_list(
+    auth: Auth, state: Data<AppState>,
+) -> Jorp<Vec<ApiToken>> {
+    auth::admin(&auth)?;
+    Ok(Json(ApiToken::list(&state.sql).await?))
+}
+
+#[derive(Debug, serde::Deserialize, utoipa::ToSchema)]
+struct AdminTokenCreateBody {
+    name: String,
+    /// channels the token may use, all of them when not set
+    channels: Option<HashSet<String>>,
+    /// operations the token may do, all but admin when not set
+    ops: Option<HashSet<Op>>,
+    /// rate limit and daily quotas, like `limit` of a config token
+    limit: Option<Limit>,
+    /// seconds until the token expires, 30 days by default, `0` for never
+    expires_in: Option<i64>,
+}
+
+#[utoipa::path(
+    post,
+    request_body = AdminTokenCreateBody,
+    responses((status = 200, body = AdminTokenIssued))
+)]
+/// Create Token
+#[post("/tokens/")]
+async fn r_tokens_create(
+    auth: Auth, state: Data<AppState>, body: Json<AdminTokenCreateBody>,
+) -> Jorp<AdminTokenIssued> {
+    let admin = auth::admin(&auth)?;
+    let conf = Config::get();
+
+    let body = body.into_inner();
+    let name = body.name.trim();
+    if name.is_empty() {
+        return crate::err!(BadRequest, "name is empty");
+    }
+    // limits and logs tell them apart as `api:` and `token:`, the same
+    // name would still confuse whoever reads them
+    if conf.tokens.contains_key(name) {
+        return crate::err!(NotUnique, "a config token has this name");
+    }
+    if let Some(chs) = &body.channels
+        && let Some(ch) = chs.iter().find(|v| !conf.channels.contains_key(*v))
+    {
+        return crate::err!(BadRequest, format!("unknown channel {ch}"));
+    }
+
+    let expires_at = expires_at(body.expires_in)?;
+    let (info, token) = ApiToken::create(
+        &state.sql,
+        name,
+        body.channels,
+        body.ops,
+        body.limit,
+        expires_at,
+    )
+    .await?;
+    log::info!("token {name} was created by {}", admin.name);
+
+    Ok(Json(AdminTokenIssued { token, info }))
+}
+
+#[derive(Debug, serde::Deserialize, utoipa::ToSchema)]
+struct AdminTokenRotateBody {
+    /// seconds until the new secret expires, 30 days by default, `0` for
+    /// never
+    expires_in: Option<i64>,
+}
+
+#[utoipa::path(
+    post,
+    params(("id" = i64, Path,)),
+    request_body = AdminTokenRotateBody,
+    responses((status = 200, body = AdminTokenIssued))
+)]
+/// Rotate Token
+///
+/// replace the secret of a token, the old one stops working right away
+#[post("/tokens/{id}/rotate/")]
+async fn r_tokens_rotate(
+    auth: Auth, state: Data<AppState>, path: Path<(i64,)>,
+    body: Json<AdminTokenRotateBody>,
+) -> Jorp<AdminTokenIssued> {
+    let admin = auth::admin(&auth)?;
+    let (id,) = path.into_inner();
+
+    let expires_at = expires_at(body.expires_in)?;
+    let (info, token) = ApiToken::rotate(&state.sql, id, expires_at).await?;
+    log::info!("token {} was rotated by {}", info.name, admin.name);
+
+    Ok(Json(AdminTokenIssued { token, info }))
+}
+
+#[utoipa::path(
+    delete,
+    params(("id" = i64, Path,)),
+    responses((status = 200))
+)]
+/// Revoke Token
+#[delete("/tokens/{id}/")]
+async fn r_tokens_revoke(
+    auth: Auth, state: Data<AppState>, path: Path<(i64,)>,
+) -> Horp {
+    let admin = auth::admin(&auth)?;
+    let (id,) = path.into_inner();
+
+    let info = ApiToken::revoke(&state.sql, id).await?;
+    log::info!("token {} was revoked by {}", info.name, admin.name);
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[utoipa::path(
+    get,
+    params(AuditFilter),
+    responses((status = 200, body = Vec<AuditEntry>))
+)]
+/// Audit Log
+///
+/// abzar requests matching every given filter, 100 per page
+#[get("/audit/")]
+async fn r_audit(
+    auth: Auth, state: Data<AppState>, filter: Query<AuditFilter>,
+) -> Jorp<Vec<AuditEntry>> {
+    auth::admin(&auth)?;
+    Ok(Json(AuditEntry::list(&state.sql, &filter).await?))
+}
+
+pub fn router() -> Scope {
+    Scope::new("/admin")
+        .service(r_tokens_list)
+        .service(r_tokens_create)
+        .service(r_tokens_rotate)
+        .service(r_tokens_revoke)
+        .service(r_audit)
+}
@@ -1 +1,2 @@
 pub mod abzar;
+pub mod admin;
@@ -0,0 +1,131 @@
+//! the audit log of abzar requests, handlers note what they know through
+//! [`Audit`] and [`record`] stores it with the outcome of the request
+
+use crate::auth::{Auth, client_ip};
+use crate::config::Config;
+use crate::models::{AppErr, AppState, AuditEntry};
+use crate::utils::sys_now;
+use actix_web::body::MessageBody;
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{FromRequest, HttpMessage, HttpRequest, web::Data};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::future::{Ready, ready};
+use std::rc::Rc;
+
+/// characters of the text kept in `text_preview`
+const PREVIEW: usize = 64;
+
+#[derive(Clone)]
+/// the audit entry of the current request
+pub struct Audit(Rc<RefCell<AuditEntry>>);
+
+impl FromRequest for Audit {
+    type Error = AppErr;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(rq: &HttpRequest, _: &mut Payload) -> Self::Future {
+        // outside of [`record`] the entry is filled in and dropped
+        let audit = rq.extensions().get::<Self>().cloned();
+        ready(Ok(audit.unwrap_or_else(|| Self::new(rq))))
+    }
+}
+
+impl Audit {
+    fn new(rq: &HttpRequest) -> Self {
+        Self(Rc::new(RefCell::new(AuditEntry {
+            at: sys_now(),
+            endpoint: rq.path().to_string(),
+            ip: client_ip(rq).map(|v| v.to_string()),
+            ..Default::default()
+        })))
+    }
+
+    /// who asked for what on which channel, noted before the auth checks
+    /// so refused requests are logged with it too
+    pub fn request(&self, auth: &Auth, channel: &str, text: Option<&str>) {
+        // the text as it was delivered, secrets stay out of the log
+        let conf = Config::get();
+        let ch = conf.channels.get(channel);
+        let text = text.map(|v| match ch.and_then(|v| v.redact.as_ref()) {
+            Some(r) => r.mask(v),
+            None => Cow::Borrowed(v),
+        });
+
+        let mut e = self.0.borrow_mut();
+        e.channel = Some(channel.to_string());
+        e.identity = Some(match (auth.bearer_key(), &auth.signed) {
+            (Some((_, key)), _) => key,
+            (None, Some(ch)) => format!("hmac:{ch}"),
+            (None, None) => "pass".to_string(),
+        });
+        if let Some(text) = text {
+            e.text_hash = Some(hex::encode(Sha256::digest(text.as_bytes())));
+            e.text_preview = Some(text.chars().take(PREVIEW).collect());
+        }
+    }
+
+    pub fn file(&self, name: Option<&str>, mime: Option<&str>, size: u64) {
+        let mut e = self.0.borrow_mut();
+        e.file_name = name.map(String::from);
+        e.file_mime = mime.map(String::from);
+        e.file_size = Some(size as i64);
+    }
+
+    /// the channel the request named, as it was given
+    pub fn channel(&self) -> Option<String> {
+        self.0.borrow().channel.clone()
+    }
+
+    pub fn message(&self, message_id: Option<i64>) {
+        self.0.borrow_mut().message_id = message_id;
+    }
+}
+
+/// store an audit entry for every request that passes through, a failed
+/// write is logged and does not fail the request
+pub async fn record(
+    rq: ServiceRequest, next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let audit = Audit::new(rq.request());
+    rq.extensions_mut().insert(audit.clone());
+    let state = rq.app_data::<Data<AppState>>().cloned();
+
+    let rs = next.call(rq).await;
+
+    let mut entry = audit.0.borrow().clone();
+    let error = match &rs {
+        Ok(rs) => {
+            entry.status = rs.status().as_u16();
+            rs.response().error()
+        }
+        Err(e) => {
+            entry.status = e.as_response_error().status_code().as_u16();
+            Some(e)
+        }
+    };
+    entry.error = error.map(|e| match e.as_error::<AppErr>() {
+        Some(e) => match serde_json::to_value(e.code()) {
+            Ok(serde_json::Value::String(v)) => v,
+            _ => "unknown".to_string(),
+        },
+        None => e.to_string(),
+    });
+    // an error that was rendered into a response by hand
+    if entry.error.is_none() && entry.status >= 400 {
+        entry.error = Some(entry.status.to_string());
+    }
+
+    match state {
+        Some(state) => {
+            if let Err(e) = entry.insert(&state.sql).await {
+                log::error!("[audit] could not record {entry:?}: {e:?}");
+            }
+        }
+        None => log::error!("[audit] no database for {entry:?}"),
+    }
+
+    rs
+}
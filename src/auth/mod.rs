@@ -0,0 +1,307 @@
+use crate::config::{Channel, Config};
+use crate::limits::{self, Limit};
+use crate::models::{ApiToken, AppErr, AppState};
+use crate::server::PeerCred;
+use actix_web::http::header;
+use actix_web::web::Data;
+use actix_web::{FromRequest, HttpMessage, HttpRequest, dev::Payload};
+use futures_util::future::LocalBoxFuture;
+use std::collections::HashSet;
+use std::net::IpAddr;
+
+mod pass;
+mod sign;
+
+pub use pass::{Pass, hash_pass};
+pub use sign::{Signed, verify_signature};
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    serde::Deserialize,
+    serde::Serialize,
+    utoipa::ToSchema
+)]
+#[serde(rename_all = "snake_case")]
+/// what a request does with a channel, tokens can be limited to some of them
+pub enum Op {
+    /// send text messages
+    Send,
+    /// send files, uploaded or from `local_dir`
+    SendFile,
+    Edit,
+    Delete,
+    /// manage api tokens, a token only has it when listed in its `ops`
+    Admin,
+}
+
+#[derive(Debug, Clone)]
+/// a valid api token from the config or the database
+pub struct Bearer {
+    /// `token` from the config, `api` from the database or `peer`, names
+    /// are only unique within a kind
+    pub kind: &'static str,
+    pub name: String,
+    /// channels the token may use, all of them when not set
+    pub channels: Option<HashSet<String>>,
+    /// operations the token may do, all but admin when not set
+    pub ops: Option<HashSet<Op>>,
+    pub limit: Option<Limit>,
+}
+
+impl Bearer {
+    /// may this token do `op` on the channel
+    pub fn allows(&self, channel: &str, op: Op) -> bool {
+        let ch = self.channels.as_ref().is_none_or(|v| v.contains(channel));
+        let op = self.ops.as_ref().is_none_or(|v| v.contains(&op));
+        ch && op
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.ops.as_ref().is_some_and(|v| v.contains(&Op::Admin))
+    }
+}
+
+/// who is making the request
+pub struct Auth {
+    /// the api token from `Authorization: Bearer <token>`
+    pub token: Option<Bearer>,
+    /// the `[peers]` entry of the local process on the unix socket
+    pub peer: Option<Bearer>,
+    /// the channel whose hmac key signed the request
+    pub signed: Option<String>,
+    /// the client address, through the trusted proxies
+    pub ip: Option<IpAddr>,
+    /// the `Content-Length` of the request, for the daily bytes
+    pub length: Option<u64>,
+}
+
+impl FromRequest for Auth {
+    type Error = AppErr;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(rq: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let signed = rq.extensions().get::<Signed>().map(|v| v.0.clone());
+        let ip = client_ip(rq);
+        let token = Self::token(rq);
+        let peer = Self::peer(rq);
+        let state = rq.app_data::<Data<AppState>>().cloned();
+        let length = rq
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok()?.parse().ok());
+
+        Box::pin(async move {
+            let token = match token? {
+                Some(token) => Some(Self::bearer(state, &token).await?),
+                None => None,
+            };
+            Ok(Self { token, peer, signed, ip, length })
+        })
+    }
+}
+
+impl Auth {
+    fn token(rq: &HttpRequest) -> Result<Option<String>, AppErr> {
+        let Some(value) = rq.headers().get(header::AUTHORIZATION) else {
+            return Ok(None);
+        };
+
+        let token = value.to_str().ok().and_then(|v| {
+            let (scheme, token) = v.split_once(' ')?;
+            scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+        });
+        match token {
+            Some(token) => Ok(Some(token.to_string())),
+            None => crate::err!(BadAuth, "invalid authorization header"),
+        }
+    }
+
+    /// requests nginx forwarded come in on the same socket, they carry
+    /// its credentials and are never trusted this way
+    fn peer(rq: &HttpRequest) -> Option<Bearer> {
+        let cred = *rq.conn_data::<PeerCred>()?;
+        let headers = rq.headers();
+        if headers.contains_key("x-forwarded-for")
+            || headers.contains_key("x-real-ip")
+        {
+            return None;
+        }
+        Config::get().peer(cred)
+    }
+
+    /// the token, or the peer when there is none, and the key its limits
+    /// and audit entries go by
+    pub fn bearer_key(&self) -> Option<(&Bearer, String)> {
+        let b = self.token.as_ref().or(self.peer.as_ref())?;
+        Some((b, format!("{}:{}", b.kind, b.name)))
+    }
+
+    /// `iris_` tokens are made with the admin api and live in the db, the
+    /// rest are in the config
+    async fn bearer(
+        state: Option<Data<AppState>>, token: &str,
+    ) -> Result<Bearer, AppErr> {
+        if token.starts_with(ApiToken::PREFIX) {
+            if let Some(state) = state
+                && let Some(t) = ApiToken::verify(&state.sql, token).await?
+            {
+                return Ok(t.bearer());
+            }
+            return crate::err!(BadAuth, "invalid token");
+        }
+
+        // plain tokens are cheap to compare, a hashed one is only checked
+        // when its prefix fits so a request costs at most one slow hash
+        let conf = Config::get();
+        for (name, t) in &conf.tokens {
+            if t.token.is_plain() && t.token.verify(token).await {
+                return Ok(t.bearer(name));
+            }
+        }
+        let hashed = conf.tokens.iter().find(|(_, t)| {
+            !t.token.is_plain()
+                && t.prefix.as_ref().is_some_and(|p| token.starts_with(p))
+        });
+        if let Some((name, t)) = hashed
+            && t.token.verify(token).await
+        {
+            return Ok(t.bearer(name));
+        }
+
+        crate::err!(BadAuth, "invalid token")
+    }
+}
+
+/// the address of the client, proxies in `trusted_proxies` and unix socket
+/// peers in `trusted_peers` (nginx) are skipped using their forwarded headers
+pub fn client_ip(rq: &HttpRequest) -> Option<IpAddr> {
+    let conf = Config::get();
+    let trusted =
+        |ip: &IpAddr| conf.trusted_proxies.iter().any(|n| n.contains(ip));
+
+    let peer = rq.peer_addr().map(|v| v.ip());
+    let proxy = match peer {
+        Some(ip) => trusted(&ip),
+        // on a unix socket, any local user could connect and make the
+        // headers up, only the proxy is believed
+        None => rq
+            .conn_data::<PeerCred>()
+            .is_some_and(|c| conf.trusted_peers.trusts(*c)),
+    };
+    if !proxy {
+        return peer;
+    }
+
+    let header = |name: &str| rq.headers().get(name)?.to_str().ok();
+    if let Some(xff) = header("x-forwarded-for") {
+        // the right most address that is not a trusted proxy,
+        // anything left of it could have been made up by the client
+        let mut last = None;
+        for ip in xff.rsplit(',') {
+            let ip = ip.trim().parse::<IpAddr>().ok()?;
+            last = Some(ip);
+            if !trusted(&ip) {
+                break;
+            }
+        }
+        return last;
+    }
+
+    if let Some(ip) = header("x-real-ip").and_then(|v| v.trim().parse().ok()) {
+        return Some(ip);
+    }
+
+    peer
+}
+
+/// find the channel and check the request may do `op` on it, with a bearer
+/// token, an hmac signature or the legacy `pass` from the body
+pub async fn channel<'a>(
+    conf: &'a Config, auth: &Auth, name: &str, pass: Option<&str>, op: Op,
+) -> Result<&'a Channel, AppErr> {
+    let Some(ch) = conf.channels.get(name) else {
+        return crate::err!(NotFound, "no channel");
+    };
+
+    if let Some(nets) = &ch.allow_ips {
+        let allowed =
+            auth.ip.is_some_and(|ip| nets.iter().any(|n| n.contains(&ip)));
+        if !allowed {
+            log::warn!("channel {name} refused client {:?}", auth.ip);
+            return crate::err!(
+                Forbidden,
+                "your address may not use this channel"
+            );
+        }
+    }
+
+    let signed = auth.signed.as_deref() == Some(name);
+    if ch.hmac_key.is_some() && !signed {
+        return crate::err!(BadAuth, "requests to this channel must be signed");
+    }
+
+    match auth.bearer_key() {
+        Some((t, key)) => {
+            if !t.allows(name, op) {
+                return crate::err!(
+                    Forbidden,
+                    format!("{key} is not allowed to do that")
+                );
+            }
+        }
+        None if signed => {}
+        None => match (&ch.pass, pass) {
+            (Some(cp), Some(p)) if cp.verify(p).await => {}
+            (_, None) => {
+                return crate::err!(BadAuth, "no token or pass was given");
+            }
+            _ => return crate::err!(NotFound, "no channel"),
+        },
+    };
+
+    Ok(ch)
+}
+
+/// charge the limits of the token and the channel, once every other check
+/// of the request passed. `bytes` is its size when known up front
+pub fn admit(
+    auth: &Auth, name: &str, ch: &Channel, bytes: Option<u64>,
+) -> Result<(), AppErr> {
+    let channel = format!("channel:{name}");
+    let mut keys = Vec::with_capacity(2);
+    if let Some((t, key)) = auth.bearer_key()
+        && let Some(limit) = &t.limit
+    {
+        keys.push((key, limit));
+    }
+    if let Some(limit) = &ch.limit {
+        keys.push((channel, limit));
+    }
+
+    let keys = keys.iter().map(|(k, l)| (k.as_str(), *l)).collect::<Vec<_>>();
+    limits::admit(&keys, bytes)
+}
+
+/// the request must come with a token or peer that lists the admin op
+pub fn admin(auth: &Auth) -> Result<&Bearer, AppErr> {
+    match auth.bearer_key() {
+        Some((t, _)) if t.is_admin() => Ok(t),
+        Some((_, key)) => {
+            crate::err!(Forbidden, format!("{key} is not an admin"))
+        }
+        None => crate::err!(BadAuth, "an admin token is needed"),
+    }
+}
+
+/// count the bytes of a sent message against the daily quotas
+pub fn used_bytes(auth: &Auth, channel: &str, bytes: u64) {
+    if let Some((_, key)) = auth.bearer_key() {
+        limits::used_bytes(&key, bytes);
+    }
+    limits::used_bytes(&format!("channel:{channel}"), bytes);
+}
@@ -0,0 +1,82 @@
+use argon2::password_hash::{PasswordHash, PasswordVerifier};
+use subtle::ConstantTimeEq;
+
+/// a channel pass as written in the config, either plain or hashed
+#[derive(Clone)]
+pub enum Pass {
+    Plain(String),
+    /// a `$argon2id$...` phc string
+    Argon2(String),
+    /// a `$2b$...` bcrypt hash
+    Bcrypt(String),
+}
+
+impl Pass {
+    pub fn parse(value: String) -> Result<Self, String> {
+        if value.starts_with("$argon2") {
+            if let Err(e) = PasswordHash::new(&value) {
+                return Err(format!("invalid argon2 hash: {e}"));
+            }
+            return Ok(Self::Argon2(value));
+        }
+
+        if value.starts_with("$2") {
+            if let Err(e) = value.parse::<bcrypt::HashParts>() {
+                return Err(format!("invalid bcrypt hash: {e}"));
+            }
+            return Ok(Self::Bcrypt(value));
+        }
+
+        Ok(Self::Plain(value))
+    }
+
+    /// cheap to check, unlike the hashes
+    pub fn is_plain(&self) -> bool {
+        matches!(self, Self::Plain(_))
+    }
+
+    /// check the given pass in constant time, the hashes take tens of ms
+    /// so they run on the blocking pool and not on the worker thread
+    pub async fn verify(&self, pass: &str) -> bool {
+        if self.is_plain() {
+            return self.verify_now(pass);
+        }
+        let (this, pass) = (self.clone(), pass.to_string());
+        actix_web::web::block(move || this.verify_now(&pass))
+            .await
+            .unwrap_or(false)
+    }
+
+    fn verify_now(&self, pass: &str) -> bool {
+        match self {
+            Self::Plain(v) => v.as_bytes().ct_eq(pass.as_bytes()).into(),
+            Self::Argon2(v) => {
+                let Ok(hash) = PasswordHash::new(v) else { return false };
+                argon2::Argon2::default()
+                    .verify_password(pass.as_bytes(), &hash)
+                    .is_ok()
+            }
+            Self::Bcrypt(v) => bcrypt::verify(pass, v).unwrap_or(false),
+        }
+    }
+}
+
+impl std::fmt::Debug for Pass {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Plain(_) => "Pass::Plain(..)",
+            Self::Argon2(_) => "Pass::Argon2(..)",
+            Self::Bcrypt(_) => "Pass::Bcrypt(..)",
+        })
+    }
+}
+
+/// an argon2id phc string for the pass, for use in the config
+pub fn hash_pass(pass: &str) -> String {
+    use argon2::password_hash::{PasswordHasher, SaltString, rand_core::OsRng};
+    let salt = SaltString::generate(&mut OsRng);
+    argon2::Argon2::default()
+        .hash_password(pass.as_bytes(), &salt)
+        .expect("argon2 hashing failed")
+        .to_string()
+}
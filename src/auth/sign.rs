@@ -0,0 +1,182 @@
+//! optional hmac-sha256 request signing for channels with an `hmac_key`
+//!
+//! a signed request carries these headers:
+//! - `X-Iris-Channel`: the channel whose key signed it
+//! - `X-Iris-Timestamp`: unix seconds, must be within `hmac_skew` of now
+//! - `X-Iris-Nonce`: a random string that is never reused
+//! - `X-Iris-Signature`: hex of
+//!   `hmac(key, "{method}\n{path}\n{timestamp}\n{nonce}\n{body}")`
+
+use crate::config::Config;
+use crate::models::AppErr;
+use actix_web::HttpMessage;
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::error::PayloadError;
+use actix_web::middleware::Next;
+use actix_web::web::{Bytes, BytesMut};
+use futures_util::{Stream, StreamExt};
+use hmac::{Hmac, Mac};
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::Mutex;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+type HmacSha256 = Hmac<sha2::Sha256>;
+
+/// signed bodies up to this size are held in memory, bigger ones go to a
+/// temp file until the signature is checked so no request pins its size
+/// in ram
+const IN_MEMORY: usize = 64 * 1024;
+
+/// the request was signed with the key of this channel
+pub struct Signed(pub String);
+
+fn header<'a>(rq: &'a ServiceRequest, name: &str) -> Result<&'a str, AppErr> {
+    match rq.headers().get(name).and_then(|v| v.to_str().ok()) {
+        Some(v) => Ok(v),
+        None => crate::err!(BadAuth, format!("{name} header is missing")),
+    }
+}
+
+/// remember the nonce, false when it was already seen within the window
+fn fresh_nonce(channel: &str, nonce: &str, now: i64, skew: i64) -> bool {
+    static SEEN: Mutex<Option<HashMap<String, i64>>> = Mutex::new(None);
+    let mut seen = SEEN.lock().unwrap_or_else(|e| e.into_inner());
+    let seen = seen.get_or_insert_default();
+
+    // a timestamp older than the window is rejected anyway,
+    // so nonces from back then can be forgotten
+    seen.retain(|_, at| *at > now - skew * 2);
+    seen.insert(format!("{channel}:{nonce}"), now).is_none()
+}
+
+/// the mac over everything but the body, which is fed in as it arrives
+fn mac(
+    key: &[u8], method: &str, path: &str, timestamp: &str, nonce: &str,
+) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("hmac takes any key");
+    mac.update(format!("{method}\n{path}\n{timestamp}\n{nonce}\n").as_bytes());
+    mac
+}
+
+/// a temp file that is gone from the directory as soon as it is open
+async fn spool() -> std::io::Result<tokio::fs::File> {
+    let name = crate::utils::rand_str(Config::TOKEN_ABC, 20);
+    let path = std::env::temp_dir().join(format!("iris-signed-{name}"));
+    let file = tokio::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .await?;
+    tokio::fs::remove_file(&path).await?;
+    Ok(file)
+}
+
+/// the spooled body as the payload of the request
+fn file_payload(file: tokio::fs::File) -> Payload {
+    let stream = futures_util::stream::unfold(Some(file), |file| async {
+        let mut file = file?;
+        let mut buf = BytesMut::with_capacity(IN_MEMORY);
+        match file.read_buf(&mut buf).await {
+            Ok(0) => None,
+            Ok(_) => Some((Ok(buf.freeze()), Some(file))),
+            Err(e) => Some((Err(PayloadError::Io(e)), None)),
+        }
+    });
+    // multipart polls again after the end, which unfold does not allow
+    let stream: Pin<Box<dyn Stream<Item = Result<Bytes, PayloadError>>>> =
+        Box::pin(stream.fuse());
+    Payload::from(stream)
+}
+
+async fn verify(rq: &mut ServiceRequest) -> Result<(), AppErr> {
+    let conf = Config::get();
+    let channel = header(rq, "x-iris-channel")?.to_string();
+    let timestamp = header(rq, "x-iris-timestamp")?.to_string();
+    let nonce = header(rq, "x-iris-nonce")?.to_string();
+    let signature = header(rq, "x-iris-signature")?;
+    let Ok(signature) = hex::decode(signature) else {
+        return crate::err!(BadAuth, "signature must be hex");
+    };
+
+    let Some(key) =
+        conf.channels.get(&channel).and_then(|v| v.hmac_key.as_ref())
+    else {
+        return crate::err!(BadAuth, "channel has no hmac key");
+    };
+
+    let Ok(ts) = timestamp.parse::<i64>() else {
+        return crate::err!(BadAuth, "invalid timestamp");
+    };
+    let now = crate::utils::sys_now();
+    let skew = conf.hmac_skew as i64;
+    if (now - ts).abs() > skew {
+        return crate::err!(BadAuth, "timestamp is outside the allowed skew");
+    }
+
+    // the whole body is needed before anything can be trusted, past a
+    // few kb it waits on disk instead of in memory
+    let limit = conf.max_file_size() as usize + 1024 * 1024;
+    let key = key.expose().as_bytes();
+    let mut mac = mac(key, rq.method().as_str(), rq.path(), &timestamp, &nonce);
+    let mut payload = rq.take_payload();
+    let mut size = 0;
+    let mut memory = BytesMut::new();
+    let mut file: Option<tokio::fs::File> = None;
+    while let Some(chunk) = payload.next().await {
+        let chunk = match chunk {
+            Ok(v) => v,
+            Err(e) => return crate::err!(BadRequest, e.to_string()),
+        };
+        size += chunk.len();
+        if size > limit {
+            return crate::err!(FileTooBig, "signed body is too big");
+        }
+        mac.update(&chunk);
+        match &mut file {
+            Some(f) => f.write_all(&chunk).await?,
+            None if size <= IN_MEMORY => memory.extend_from_slice(&chunk),
+            None => {
+                let mut f = spool().await?;
+                f.write_all(&memory).await?;
+                f.write_all(&chunk).await?;
+                memory = BytesMut::new();
+                file = Some(f);
+            }
+        }
+    }
+
+    if mac.verify_slice(&signature).is_err() {
+        return crate::err!(BadAuth, "invalid signature");
+    }
+
+    if !fresh_nonce(&channel, &nonce, now, skew) {
+        return crate::err!(BadAuth, "nonce was already used");
+    }
+
+    match file {
+        None => rq.set_payload(memory.freeze().into()),
+        Some(mut f) => {
+            f.rewind().await?;
+            rq.set_payload(file_payload(f));
+        }
+    }
+    rq.extensions_mut().insert(Signed(channel));
+    Ok(())
+}
+
+/// check the signature of requests that carry one, the handlers then
+/// see which channel signed it through [`super::Auth`]
+pub async fn verify_signature(
+    mut rq: ServiceRequest, next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    if rq.headers().contains_key("x-iris-signature")
+        && let Err(e) = verify(&mut rq).await
+    {
+        return Ok(rq.error_response(e).map_into_right_body());
+    }
+
+    next.call(rq).await.map(ServiceResponse::map_into_left_body)
+}
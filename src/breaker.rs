@@ -0,0 +1,172 @@
+//! circuit breakers for the delivery backends. after `failures` outages in
+//! a row a backend is not tried for `open_for` seconds and requests fail
+//! right away. after that a single probe is let through and decides if it
+//! closes or opens again, the rest keep failing until it is back
+
+use crate::config::Config;
+use crate::metrics;
+use crate::models::AppErr;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// how long the probe of a half open breaker may take to come back
+const PROBE_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Breaker {
+    /// outages in a row that open the breaker
+    #[serde(default = "Breaker::default_failures")]
+    pub failures: u32,
+    /// seconds the breaker stays open
+    #[serde(default = "Breaker::default_open_for")]
+    pub open_for: u64,
+}
+
+impl Default for Breaker {
+    fn default() -> Self {
+        Self {
+            failures: Self::default_failures(),
+            open_for: Self::default_open_for(),
+        }
+    }
+}
+
+impl Breaker {
+    fn default_failures() -> u32 {
+        5
+    }
+
+    fn default_open_for() -> u64 {
+        30
+    }
+
+    pub fn check(&self) -> Result<(), String> {
+        if self.failures == 0 {
+            return Err("breaker.failures must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct State {
+    failures: u32,
+    open_until: Option<Instant>,
+    /// when the probe of a half open breaker was let through
+    probe: Option<Instant>,
+}
+
+static STATE: Mutex<Option<HashMap<String, State>>> = Mutex::new(None);
+
+fn state<T>(f: impl FnOnce(&mut HashMap<String, State>) -> T) -> T {
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    f(state.get_or_insert_default())
+}
+
+/// the breaker of a backend at this url, e.g. `telegram:api.telegram.org`
+pub fn key(kind: &str, url: &reqwest::Url) -> String {
+    match url.port() {
+        Some(port) => format!("{kind}:{}:{port}", url.host_str().unwrap_or("")),
+        None => format!("{kind}:{}", url.host_str().unwrap_or("")),
+    }
+}
+
+/// the probe of a half open breaker, if this request is it. when it is
+/// dropped before anything was recorded, e.g. the message was refused
+/// before any call went out, the next request gets to probe instead
+#[derive(Debug, Default)]
+#[must_use]
+pub struct Probe(Option<(String, Instant)>);
+
+impl Drop for Probe {
+    fn drop(&mut self) {
+        let Some((key, at)) = self.0.take() else { return };
+        state(|s| {
+            if let Some(v) = s.get_mut(&key)
+                && v.probe == Some(at)
+            {
+                v.probe = None;
+            }
+        })
+    }
+}
+
+/// fail fast while the breaker of the backend is open, or while the probe
+/// after it is still out
+pub fn admit(key: &str) -> Result<Probe, AppErr> {
+    let now = Instant::now();
+    let left = state(|s| {
+        let Some(v) = s.get_mut(key) else { return Ok(Probe::default()) };
+        let Some(until) = v.open_until else { return Ok(Probe::default()) };
+        if let Some(left) = until.checked_duration_since(now) {
+            return Err(left);
+        }
+        // a probe that never came back, e.g. its upload broke, gets a
+        // successor after a while so the breaker does not stay stuck
+        match v.probe {
+            Some(at) if now.duration_since(at) < PROBE_TIMEOUT => {
+                Err(Duration::ZERO)
+            }
+            _ => {
+                v.probe = Some(now);
+                Ok(Probe(Some((key.to_string(), now))))
+            }
+        }
+    });
+
+    left.map_err(|left| {
+        crate::err!(
+            r,
+            CircuitOpen,
+            format!("{key} is down, not trying it for {}s", left.as_secs() + 1)
+        )
+        .retry_after(left.as_secs() + 1)
+    })
+}
+
+/// note how an http call to the backend went. only no connection, a
+/// timeout or a 5xx count against it. a rejected request still means it
+/// is up, a 429 too since telegram limits each chat on its own, and a
+/// body that failed on our side (an aborted or too big upload) says
+/// nothing about it
+pub fn outcome(
+    conf: &Config, key: &str, r: &reqwest::Result<reqwest::Response>,
+) {
+    match r {
+        Ok(rs) => {
+            record(conf, key, !rs.status().is_server_error());
+        }
+        Err(e) if e.is_connect() || e.is_timeout() => record(conf, key, false),
+        Err(_) => {}
+    }
+}
+
+/// note how a call to the backend went, `ok` is false only for outages
+pub fn record(conf: &Config, key: &str, ok: bool) {
+    state(|s| {
+        if ok {
+            if let Some(v) = s.remove(key)
+                && v.open_until.is_some()
+            {
+                log::info!("[breaker] {key} is back, closed");
+                metrics::set("iris_breaker_open", &[("backend", key)], 0.0);
+            }
+            return;
+        }
+
+        let v = s.entry(key.to_string()).or_default();
+        v.failures += 1;
+        v.probe = None;
+        if v.failures >= conf.breaker.failures {
+            let wait = Duration::from_secs(conf.breaker.open_for);
+            v.open_until = Some(Instant::now() + wait);
+            metrics::set("iris_breaker_open", &[("backend", key)], 1.0);
+            log::warn!(
+                "[breaker] {key} failed {} times in a row, open for {wait:?}",
+                v.failures
+            );
+        }
+    })
+}
@@ -0,0 +1,437 @@
+use crate::config::{Config, Target};
+use crate::delivery::telegram;
+use crate::delivery::{self, Document, Message, ParseMode, Severity, Source};
+use crate::proxy;
+use std::io::{BufRead, IsTerminal, Read, Write};
+use std::path::PathBuf;
+
+/// subcommands of the iris binary, without one the server is started
+pub enum Command {
+    /// `iris hash-pass` reads a pass from stdin and prints its argon2 hash
+    HashPass,
+    /// `iris check-config [--probe-threads]` checks the config file, the
+    /// bot token and every telegram chat against the bot api without
+    /// changing anything. `--probe-threads` also sends a typing action to
+    /// each thread, which shows up in it for a few seconds
+    CheckConfig { probe_threads: bool },
+    /// `iris send --channel <name> [--text <text>] ...` sends a message
+    /// through a running iris or straight from this config
+    Send(SendArgs),
+}
+
+impl Command {
+    pub fn parse() -> Result<Option<Self>, String> {
+        Self::parse_from(std::env::args().skip(1))
+    }
+
+    pub fn parse_from(
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<Option<Self>, String> {
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-c" | "--config" => {
+                    args.next();
+                }
+                "hash-pass" => return Ok(Some(Self::HashPass)),
+                "check-config" => {
+                    let mut probe_threads = false;
+                    while let Some(arg) = args.next() {
+                        match arg.as_str() {
+                            "--probe-threads" => probe_threads = true,
+                            "-c" | "--config" => {
+                                args.next();
+                            }
+                            _ => {
+                                return Err(format!(
+                                    "unknown argument {arg}\nusage: iris \
+                                     check-config [--probe-threads] \
+                                     [-c <config>]"
+                                ));
+                            }
+                        }
+                    }
+                    return Ok(Some(Self::CheckConfig { probe_threads }));
+                }
+                "send" => {
+                    return SendArgs::parse(args).map(Self::Send).map(Some);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+#[derive(Default)]
+pub struct SendArgs {
+    pub channel: String,
+    /// read from stdin when not given or `-`
+    pub text: Option<String>,
+    pub file: Option<PathBuf>,
+    pub parse_mode: Option<String>,
+    pub severity: Option<String>,
+    /// `--token`, or `IRIS_TOKEN` from the environment
+    pub token: Option<String>,
+    /// `--pass`, or `IRIS_PASS` from the environment
+    pub pass: Option<String>,
+    /// `http://host:port` or `unix:/path/to/iris.sock` of a running iris
+    pub url: Option<String>,
+    /// skip the server and send with the channels of the config
+    pub direct: bool,
+}
+
+impl SendArgs {
+    const USAGE: &str = "usage: iris send --channel <name> [--text <text>] \
+        [--file <path>] [--parse-mode html|markdown|markdownv2] \
+        [--severity <level>] [--token <token>] [--pass <pass>] \
+        [--url <http://..|unix:/..>] [--direct] [-c <config>]";
+
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut sa = Self::default();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or(format!("{arg} needs a value\n{}", Self::USAGE))
+            };
+            match arg.as_str() {
+                "--channel" => sa.channel = value()?,
+                "--text" => sa.text = Some(value()?),
+                "--file" => sa.file = Some(value()?.into()),
+                "--parse-mode" => sa.parse_mode = Some(value()?),
+                "--severity" => sa.severity = Some(value()?),
+                "--token" => sa.token = Some(value()?),
+                "--pass" => sa.pass = Some(value()?),
+                "--url" => sa.url = Some(value()?),
+                "--direct" => sa.direct = true,
+                "-c" | "--config" => {
+                    value()?;
+                }
+                _ => {
+                    return Err(format!(
+                        "unknown argument {arg}\n{}",
+                        Self::USAGE
+                    ));
+                }
+            }
+        }
+
+        if sa.channel.is_empty() {
+            return Err(format!("--channel is missing\n{}", Self::USAGE));
+        }
+        if sa.direct && sa.url.is_some() {
+            return Err("--direct and --url do not go together".to_string());
+        }
+        sa.token = sa.token.or_else(|| std::env::var("IRIS_TOKEN").ok());
+        sa.pass = sa.pass.or_else(|| std::env::var("IRIS_PASS").ok());
+        Ok(sa)
+    }
+}
+
+/// run the command and give back the exit code
+pub async fn run(cmd: Command) -> i32 {
+    match cmd {
+        Command::HashPass => hash_pass(),
+        Command::CheckConfig { probe_threads } => {
+            check_config(probe_threads).await
+        }
+        // report a broken config instead of panicking in Config::get
+        Command::Send(args)
+            if args.direct
+                && let Err(e) = Config::load() =>
+        {
+            eprintln!("{e}");
+            1
+        }
+        Command::Send(args) => match send(args).await {
+            Ok(Some(id)) => {
+                println!("{id}");
+                0
+            }
+            Ok(None) => 0,
+            Err(e) => {
+                eprintln!("{e}");
+                1
+            }
+        },
+    }
+}
+
+fn hash_pass() -> i32 {
+    let stdin = std::io::stdin();
+    if stdin.is_terminal() {
+        eprint!("pass: ");
+        let _ = std::io::stderr().flush();
+    }
+
+    let mut pass = String::new();
+    if let Err(e) = stdin.lock().read_line(&mut pass) {
+        eprintln!("could not read the pass: {e}");
+        return 1;
+    }
+
+    let pass = pass.trim_end_matches(['\r', '\n']);
+    if pass.is_empty() {
+        eprintln!("the pass is empty");
+        return 1;
+    }
+
+    println!("{}", crate::auth::hash_pass(pass));
+    0
+}
+
+async fn check_config(probe_threads: bool) -> i32 {
+    let conf = match Config::load() {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{e}");
+            return 1;
+        }
+    };
+
+    let mut failed = false;
+    for (what, result) in check(&conf, probe_threads).await {
+        match result {
+            Ok(v) => println!("ok    {what}: {v}"),
+            Err(e) => {
+                failed = true;
+                println!("FAIL  {what}: {e}");
+            }
+        }
+    }
+
+    i32::from(failed)
+}
+
+/// what was checked and how it went, the channels sorted by name. the
+/// threads are only probed when asked to, the probe shows in them
+pub async fn check(
+    conf: &Config, probe_threads: bool,
+) -> Vec<(String, Result<String, String>)> {
+    let mut report = Vec::new();
+    let bot = match telegram::get_me(conf).await {
+        Ok(v) => v,
+        Err(e) => {
+            let e = format!("the tel_token does not work: {}", e.reason());
+            report.push(("bot".to_string(), Err(e)));
+            return report;
+        }
+    };
+    let username = bot.username.as_deref().unwrap_or_default();
+    report.push(("bot".to_string(), Ok(format!("@{username}"))));
+
+    for p in &conf.proxies {
+        let result = proxy::check(conf, p).await.map(|_| "works".to_string());
+        report.push((format!("proxy {}", p.name), result));
+    }
+
+    let mut names = conf.channels.keys().collect::<Vec<_>>();
+    names.sort();
+    for name in names {
+        let result = match &conf.channels[name].target {
+            Target::Telegram { chat, thread, proxies } => {
+                let (via, thread) = (proxies.as_deref(), thread.as_deref());
+                check_chat(conf, via, bot.id, chat, thread, probe_threads).await
+            }
+            _ => Ok("not a telegram channel, skipped".to_string()),
+        };
+        report.push((format!("channel {name}"), result));
+    }
+
+    report
+}
+
+async fn check_chat(
+    conf: &Config, via: telegram::Via<'_>, bot: i64, chat: &str,
+    thread: Option<&str>, probe: bool,
+) -> Result<String, String> {
+    let info = telegram::get_chat(conf, via, chat)
+        .await
+        .map_err(|e| format!("chat {chat}: {}", e.reason()))?;
+
+    // the bot is not a member of a private chat, it just needs /start
+    if info.kind != "private" {
+        let member = telegram::get_chat_member(conf, via, chat, bot)
+            .await
+            .map_err(|e| format!("bot membership: {}", e.reason()))?;
+        if !member.can_post(&info.kind) {
+            return Err(format!(
+                "the bot can not post in this {}, it is {}",
+                info.kind, member.status
+            ));
+        }
+    }
+
+    if let Some(thread) = thread {
+        if !info.is_forum {
+            return Err(format!("thread {thread} is set but topics are off"));
+        }
+        // the bot api can not look a topic up, only post to it
+        if probe {
+            telegram::send_typing(conf, via, chat, thread)
+                .await
+                .map_err(|e| format!("thread {thread}: {}", e.reason()))?;
+        }
+    }
+
+    let name = info.title.or(info.username).unwrap_or_default();
+    Ok(match (thread, probe) {
+        (Some(thread), true) => {
+            format!("{name} ({}), thread {thread}", info.kind)
+        }
+        (Some(thread), false) => format!(
+            "{name} ({}), thread {thread} not probed, see --probe-threads",
+            info.kind
+        ),
+        (None, _) => format!("{name} ({})", info.kind),
+    })
+}
+
+const PARSE_MODES: &[&str] = &["Markdown", "MarkdownV2", "Html"];
+const SEVERITIES: &[&str] = &["debug", "info", "warning", "error", "critical"];
+
+/// the name the api takes for a value given in any case
+fn canonical(
+    value: &str, names: &[&'static str],
+) -> Result<&'static str, String> {
+    match names.iter().find(|v| v.eq_ignore_ascii_case(value)) {
+        Some(v) => Ok(v),
+        None => Err(format!("{value} is not one of {}", names.join(", "))),
+    }
+}
+
+fn stdin_text() -> Result<String, String> {
+    let mut stdin = std::io::stdin();
+    if stdin.is_terminal() {
+        return Err("no --text was given and nothing is piped in".to_string());
+    }
+    let mut text = String::new();
+    stdin
+        .read_to_string(&mut text)
+        .map_err(|e| format!("could not read stdin: {e}"))?;
+    Ok(text)
+}
+
+/// send the message, gives back the telegram message id when there is one
+pub async fn send(args: SendArgs) -> Result<Option<i64>, String> {
+    let text = match args.text.as_deref() {
+        None | Some("-") => stdin_text()?,
+        Some(v) => v.to_string(),
+    };
+    let parse_mode = args
+        .parse_mode
+        .as_deref()
+        .map(|v| canonical(v, PARSE_MODES))
+        .transpose()?;
+    let severity = args
+        .severity
+        .as_deref()
+        .map(|v| canonical(v, SEVERITIES))
+        .transpose()?;
+
+    if args.direct {
+        return send_direct(&args, &text, parse_mode, severity).await;
+    }
+
+    // the first listener of the config, or where iris listens without one
+    let url = args.url.clone().unwrap_or_else(|| match Config::load() {
+        Ok(conf) => conf.server.url(),
+        Err(_) => crate::server::Server::default().url(),
+    });
+    let (client, base) = match url.strip_prefix("unix:") {
+        // any host does over a socket
+        Some(path) => (
+            reqwest::Client::builder().unix_socket(path).build(),
+            "http://localhost",
+        ),
+        None => (reqwest::Client::builder().build(), url.as_str()),
+    };
+    let client = client.map_err(|e| format!("http client: {e}"))?;
+    let base = base.trim_end_matches('/');
+
+    let mut fields = vec![("channel", args.channel.clone()), ("text", text)];
+    let optional = [
+        ("pass", args.pass.clone()),
+        ("parse_mode", parse_mode.map(String::from)),
+        ("severity", severity.map(String::from)),
+    ];
+    fields.extend(optional.into_iter().filter_map(|(k, v)| Some((k, v?))));
+
+    let rq = match &args.file {
+        Some(path) => {
+            let mut form = reqwest::multipart::Form::new();
+            for (k, v) in fields {
+                form = form.text(k, v);
+            }
+            // the file goes last, iris streams it on as it arrives
+            let part = reqwest::multipart::Part::file(path)
+                .await
+                .map_err(|e| format!("could not open {path:?}: {e}"))?;
+            let url = format!("{base}/api/abzar/send-file/");
+            client.post(url).multipart(form.part("file", part))
+        }
+        None => {
+            let body: serde_json::Map<_, _> =
+                fields.into_iter().map(|(k, v)| (k.into(), v.into())).collect();
+            client.post(format!("{base}/api/abzar/send/")).json(&body)
+        }
+    };
+    let rq = match &args.token {
+        Some(token) => rq.bearer_auth(token),
+        None => rq,
+    };
+
+    let rs = rq.send().await.map_err(|e| format!("{url}: {e}"))?;
+    let status = rs.status();
+    let body: serde_json::Value =
+        rs.json().await.map_err(|e| format!("bad response from iris: {e}"))?;
+    if !status.is_success() {
+        return Err(format!("iris answered {status}: {body}"));
+    }
+
+    Ok(body["message_id"].as_i64())
+}
+
+/// a name from [`canonical`] as the enum it names
+fn named<T: serde::de::DeserializeOwned>(name: &str) -> T {
+    serde_json::from_value(name.into()).expect("a canonical name")
+}
+
+async fn send_direct(
+    args: &SendArgs, text: &str, parse_mode: Option<&str>,
+    severity: Option<&str>,
+) -> Result<Option<i64>, String> {
+    let conf = Config::get();
+    let Some(ch) = conf.channels.get(&args.channel) else {
+        return Err(format!("no channel {} in the config", args.channel));
+    };
+
+    let path = match &args.file {
+        Some(v) => Some(
+            std::fs::canonicalize(v)
+                .map_err(|e| format!("could not open {v:?}: {e}"))?,
+        ),
+        None => None,
+    };
+    // the same limit as the http routes
+    if let Some(path) = &path {
+        let meta = std::fs::metadata(path)
+            .map_err(|e| format!("could not open {path:?}: {e}"))?;
+        let max = ch.target.max_file_size(&conf);
+        if meta.len() >= max {
+            return Err(format!("max file size is {max} bytes"));
+        }
+    }
+    let msg = Message {
+        text,
+        parse_mode: parse_mode.map(named::<ParseMode>),
+        severity: severity.map(named::<Severity>),
+        document: path.as_deref().map(|path| Document {
+            name: path.file_name().and_then(|v| v.to_str()),
+            mime: None,
+            source: Source::Local(path),
+        }),
+    };
+
+    delivery::send(ch, msg).await.map_err(|e| e.reason())
+}
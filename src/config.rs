@@ -1,23 +1,142 @@
-// use std::path::{Path, PathBuf};
-use std::{collections::HashMap, str::FromStr, sync::OnceLock};
+use crate::auth::{Bearer, Op, Pass};
+use crate::limits::Limit;
+use crate::proxy::Proxy;
+use crate::redact::Redactor;
+use crate::secret::Secret;
+use arc_swap::ArcSwap;
+use ipnet::IpNet;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+use std::{str::FromStr, time::Duration};
 
 mod config_toml {
-    use std::{collections::HashMap, path::PathBuf};
+    use crate::limits::Limit;
+    use crate::secret::Secret;
+    use std::hash::{DefaultHasher, Hash, Hasher};
+    use std::{collections::HashMap, path::Path, path::PathBuf};
 
     #[derive(Debug, serde::Deserialize)]
     pub struct Channel {
-        pub chat: String,
+        pub chat: Option<String>,
         pub thread: Option<String>,
-        pub pass: String,
+        pub email: Option<Vec<String>>,
+        pub subject: Option<String>,
+        pub ntfy: Option<Ntfy>,
+        pub gotify: Option<Gotify>,
+        pub pass: Option<Secret>,
+        pub hmac_key: Option<Secret>,
+        pub allow_ips: Option<Vec<String>>,
+        pub limit: Option<Limit>,
+        pub redact: Option<crate::redact::Redact>,
+        pub proxies: Option<Vec<String>>,
+        pub fallback: Option<String>,
     }
 
     #[derive(Debug, serde::Deserialize)]
+    pub struct Token {
+        pub token: Secret,
+        pub prefix: Option<String>,
+        pub channels: Option<Vec<String>>,
+        pub ops: Option<Vec<crate::auth::Op>>,
+        pub limit: Option<Limit>,
+    }
+
+    #[derive(Debug, serde::Deserialize)]
+    pub struct Proxy {
+        pub name: String,
+        /// `socks5://`, `socks5h://`, `http://` or `https://`, may hold
+        /// a user and pass
+        pub url: Secret,
+    }
+
+    #[derive(Debug, serde::Deserialize)]
+    pub struct Peer {
+        pub user: Option<String>,
+        pub group: Option<String>,
+        pub channels: Option<Vec<String>>,
+        pub ops: Option<Vec<crate::auth::Op>>,
+        pub limit: Option<Limit>,
+    }
+
+    #[derive(Debug, serde::Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct TrustedPeers {
+        #[serde(default)]
+        pub users: Vec<String>,
+        #[serde(default)]
+        pub groups: Vec<String>,
+    }
+
+    #[derive(Debug, serde::Deserialize)]
+    pub struct Ntfy {
+        pub url: String,
+        pub topic: String,
+        pub token: Option<Secret>,
+        pub title: Option<String>,
+        pub tags: Option<Vec<String>>,
+    }
+
+    #[derive(Debug, serde::Deserialize)]
+    pub struct Gotify {
+        pub url: String,
+        pub token: Secret,
+    }
+
+    #[derive(Debug, Default, Clone, Copy, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum SmtpTls {
+        None,
+        #[default]
+        Starttls,
+        Tls,
+    }
+
+    #[derive(Debug, serde::Deserialize)]
+    pub struct Smtp {
+        pub host: String,
+        pub port: Option<u16>,
+        #[serde(default)]
+        pub tls: SmtpTls,
+        pub user: Option<String>,
+        pub pass: Option<Secret>,
+        pub from: String,
+        /// bytes, relays turn down bigger mails anyway
+        pub max_attachment: Option<u64>,
+    }
+
+    #[derive(Debug, serde::Deserialize)]
+    /// every [`Secret`] can be given as `env:NAME`, `file:/path` or
+    /// `cred:NAME` instead of the value itself
     pub struct ConfigToml {
-        pub tel_token: String,
+        pub tel_token: Secret,
+        pub tel_api: Option<String>,
+        #[serde(default)]
+        pub tel_local: bool,
+        #[serde(default)]
+        pub proxies: Vec<Proxy>,
+        pub local_dir: Option<PathBuf>,
+        pub smtp: Option<Smtp>,
         pub channels: HashMap<String, Channel>,
+        #[serde(default)]
+        pub tokens: HashMap<String, Token>,
+        #[serde(default)]
+        pub peers: HashMap<String, Peer>,
+        pub hmac_skew: Option<u64>,
+        pub trusted_proxies: Option<Vec<String>>,
+        pub trusted_peers: Option<TrustedPeers>,
+        pub db: Option<PathBuf>,
+        pub server: Option<crate::server::Server>,
+        pub breaker: Option<crate::breaker::Breaker>,
+        pub log: Option<crate::logger::Log>,
+        /// a hash of each channel table and the secrets it resolves to,
+        /// to tell what a reload changed
+        #[serde(skip)]
+        pub sources: HashMap<String, u64>,
     }
 
-    fn path() -> PathBuf {
+    pub fn path() -> PathBuf {
         #[cfg(debug_assertions)]
         const DEFAULT: &str = "config.local.toml";
 
@@ -36,42 +155,446 @@ mod config_toml {
         PathBuf::from(path)
     }
 
-    pub fn get() -> ConfigToml {
+    pub fn parse(data: &str, path: &Path) -> Result<ConfigToml, String> {
+        let err = |e| format!("invalid toml config file: {path:?}\n{e}");
+        let mut ct: ConfigToml = toml::from_str(data).map_err(err)?;
+        let table: toml::Table = toml::from_str(data).map_err(err)?;
+
+        if let Some(toml::Value::Table(channels)) = table.get("channels") {
+            for (name, value) in channels {
+                let mut hasher = DefaultHasher::new();
+                value.to_string().hash(&mut hasher);
+                // a `file:` or `cred:` secret changes without the table
+                if let Some(ch) = ct.channels.get(name) {
+                    let secrets = [
+                        ch.pass.as_ref(),
+                        ch.hmac_key.as_ref(),
+                        ch.ntfy.as_ref().and_then(|v| v.token.as_ref()),
+                        ch.gotify.as_ref().map(|v| &v.token),
+                    ];
+                    for v in secrets {
+                        v.map(|v| v.expose()).hash(&mut hasher);
+                    }
+                }
+                ct.sources.insert(name.clone(), hasher.finish());
+            }
+        }
+
+        Ok(ct)
+    }
+
+    pub fn get() -> Result<ConfigToml, String> {
         let path = path();
         log::info!("reading config at: {path:?}");
         let data = match std::fs::read_to_string(&path) {
             Ok(v) => v,
-            Err(e) => panic!("could not read config at: {path:?}\n{e:#?}"),
+            Err(e) => {
+                return Err(format!("could not read config at: {path:?}\n{e}"));
+            }
         };
 
-        match toml::from_str(&data) {
-            Ok(v) => v,
-            Err(e) => panic!("invalid toml config file: {path:?}\n{e:#?}"),
+        parse(&data, &path)
+    }
+}
+
+pub use config_toml::{Smtp as SmtpToml, SmtpTls};
+
+#[derive(Debug)]
+/// where the messages of a channel are delivered
+pub enum Target {
+    Telegram {
+        chat: String,
+        thread: Option<String>,
+        /// names of the proxies to use, in order, all when not set
+        proxies: Option<Vec<String>>,
+    },
+    Email {
+        to: Vec<lettre::message::Mailbox>,
+        subject: Option<String>,
+    },
+    Ntfy {
+        url: reqwest::Url,
+        token: Option<Secret>,
+        title: Option<String>,
+        tags: Vec<String>,
+    },
+    Gotify {
+        url: reqwest::Url,
+        token: Secret,
+    },
+}
+
+impl Target {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Telegram { .. } => "telegram",
+            Self::Email { .. } => "email",
+            Self::Ntfy { .. } => "ntfy",
+            Self::Gotify { .. } => "gotify",
+        }
+    }
+
+    /// the biggest file this target takes, email is held in memory and
+    /// relays refuse big mails so it gets a much smaller one
+    pub fn max_file_size(&self, conf: &Config) -> u64 {
+        match (self, &conf.smtp) {
+            (Self::Email { .. }, Some(smtp)) => {
+                smtp.max_attachment.min(conf.max_file_size())
+            }
+            _ => conf.max_file_size(),
+        }
+    }
+
+    /// the circuit breaker this target goes through, none for email when
+    /// there is no smtp relay
+    pub fn breaker(&self, conf: &Config) -> Option<String> {
+        match self {
+            Self::Telegram { .. } => {
+                Some(crate::breaker::key("telegram", conf.tel_base.expose()))
+            }
+            Self::Email { .. } => conf.smtp.as_ref().map(|v| v.breaker.clone()),
+            Self::Ntfy { url, .. } => Some(crate::breaker::key("ntfy", url)),
+            Self::Gotify { url, .. } => {
+                Some(crate::breaker::key("gotify", url))
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Channel {
+    /// legacy auth with a `pass` in the body, tokens are the way to go
+    pub pass: Option<Pass>,
+    /// requests must be signed with this key, see [`crate::auth::Signed`]
+    pub hmac_key: Option<Secret>,
+    /// client addresses that may use the channel, anyone when not set
+    pub allow_ips: Option<Vec<IpNet>>,
+    pub limit: Option<Limit>,
+    /// masks or refuses secrets in the text before it is sent
+    pub redact: Option<Redactor>,
+    /// where messages go while the backend of this one is down
+    pub fallback: Option<String>,
+    pub target: Target,
+    source: u64,
+}
+
+impl Channel {
+    fn new(
+        name: &str, ch: config_toml::Channel, smtp: bool, proxies: &[Proxy],
+        source: u64,
+    ) -> Result<Self, String> {
+        let url = |base: &str, path: &str| {
+            let url = format!("{}/{path}", base.trim_end_matches('/'));
+            reqwest::Url::from_str(&url)
+                .map_err(|e| format!("channel {name}: bad url {url}: {e}"))
+        };
+
+        let mut targets = Vec::with_capacity(1);
+        if let Some(names) = &ch.proxies {
+            if ch.chat.is_none() {
+                return Err(format!(
+                    "channel {name}: proxies are for telegram"
+                ));
+            }
+            if let Some(v) =
+                names.iter().find(|v| !proxies.iter().any(|p| &p.name == *v))
+            {
+                return Err(format!("channel {name}: unknown proxy {v}"));
+            }
+        }
+        if let Some(chat) = ch.chat {
+            targets.push(Target::Telegram {
+                chat,
+                thread: ch.thread,
+                proxies: ch.proxies,
+            });
+        }
+        if let Some(email) = ch.email {
+            if !smtp {
+                return Err(format!(
+                    "channel {name} uses email but [smtp] is not set"
+                ));
+            }
+            if email.is_empty() {
+                return Err(format!("channel {name} has an empty email list"));
+            }
+            let to = email
+                .iter()
+                .map(|v| {
+                    v.parse().map_err(|e| {
+                        format!("channel {name}: bad email {v}: {e}")
+                    })
+                })
+                .collect::<Result<_, _>>()?;
+            targets.push(Target::Email { to, subject: ch.subject });
+        }
+        if let Some(n) = ch.ntfy {
+            let url = url(&n.url, &n.topic)?;
+            let tags = n.tags.unwrap_or_default();
+            let (token, title) = (n.token, n.title);
+            targets.push(Target::Ntfy { url, token, title, tags });
+        }
+        if let Some(g) = ch.gotify {
+            let url = url(&g.url, "message")?;
+            targets.push(Target::Gotify { url, token: g.token });
+        }
+
+        let Some(target) = targets.pop() else {
+            return Err(format!(
+                "channel {name} has no chat, email, ntfy or gotify"
+            ));
+        };
+        if !targets.is_empty() {
+            return Err(format!(
+                "channel {name} must set only one of chat, email, ntfy or gotify"
+            ));
+        }
+
+        let pass = ch
+            .pass
+            .map(|v| Pass::parse(v.expose().clone()))
+            .transpose()
+            .map_err(|e| format!("channel {name}: {e}"))?;
+
+        let owner = format!("channel {name}");
+        let allow_ips = ch.allow_ips.map(|v| nets(&owner, v)).transpose()?;
+        let redact = ch.redact.map(|v| Redactor::new(&owner, v)).transpose()?;
+
+        Ok(Self {
+            pass,
+            hmac_key: ch.hmac_key,
+            allow_ips,
+            limit: ch.limit,
+            redact,
+            fallback: ch.fallback,
+            target,
+            source,
+        })
+    }
+}
+
+/// parse a list of cidr ranges, a bare address is a range of one
+fn nets(owner: &str, list: Vec<String>) -> Result<Vec<IpNet>, String> {
+    list.iter()
+        .map(|v| {
+            if let Ok(net) = v.parse() {
+                return Ok(net);
+            }
+            match v.parse::<IpAddr>() {
+                Ok(ip) => Ok(IpNet::from(ip)),
+                Err(e) => Err(format!("{owner}: invalid ip range {v}: {e}")),
+            }
+        })
+        .collect()
+}
+
+fn known_channels(
+    owner: &str, list: &Option<Vec<String>>,
+    channels: &HashMap<String, Channel>,
+) -> Result<(), String> {
+    for ch in list.iter().flatten() {
+        if !channels.contains_key(ch) {
+            return Err(format!("{owner}: unknown channel {ch}"));
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+/// an api token, sent as `Authorization: Bearer <token>`
+pub struct Token {
+    pub token: Pass,
+    /// the start of the token that is not secret, a hashed token is only
+    /// checked when the bearer token starts with it
+    pub prefix: Option<String>,
+    /// channels the token may use, all of them when not set
+    pub channels: Option<HashSet<String>>,
+    /// operations the token may do, all but admin when not set
+    pub ops: Option<HashSet<Op>>,
+    pub limit: Option<Limit>,
+}
+
+impl Token {
+    fn new(
+        name: &str, t: config_toml::Token, channels: &HashMap<String, Channel>,
+    ) -> Result<Self, String> {
+        let token = Pass::parse(t.token.expose().clone())
+            .map_err(|e| format!("token {name}: {e}"))?;
+        let api = crate::models::ApiToken::PREFIX;
+        match (&token, t.prefix.as_deref()) {
+            (Pass::Plain(v), _) if v.starts_with(api) => {
+                return Err(format!("token {name}: {api} is for api tokens"));
+            }
+            (_, Some(p)) if p.starts_with(api) => {
+                return Err(format!("token {name}: {api} is for api tokens"));
+            }
+            (Pass::Plain(_), _) => {}
+            (_, None | Some("")) => {
+                return Err(format!(
+                    "token {name}: a hashed token needs a prefix, the start \
+                     of the token that is not secret"
+                ));
+            }
+            (_, Some(_)) => {}
+        }
+
+        known_channels(&format!("token {name}"), &t.channels, channels)?;
+
+        Ok(Self {
+            token,
+            prefix: t.prefix,
+            channels: t.channels.map(HashSet::from_iter),
+            ops: t.ops.map(HashSet::from_iter),
+            limit: t.limit,
+        })
+    }
+
+    pub fn bearer(&self, name: &str) -> Bearer {
+        Bearer {
+            kind: "token",
+            name: name.to_string(),
+            channels: self.channels.clone(),
+            ops: self.ops.clone(),
+            limit: self.limit.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+/// a local process on the unix socket, trusted by its uid or gid
+pub struct Peer {
+    pub uid: Option<u32>,
+    /// only the primary group of the process is known
+    pub gid: Option<u32>,
+    /// channels the peer may use, all of them when not set
+    pub channels: Option<HashSet<String>>,
+    /// operations the peer may do, all but admin when not set
+    pub ops: Option<HashSet<Op>>,
+    pub limit: Option<Limit>,
+}
+
+impl Peer {
+    fn new(
+        name: &str, p: config_toml::Peer, channels: &HashMap<String, Channel>,
+    ) -> Result<Self, String> {
+        let owner = format!("peer {name}");
+        if p.user.is_some() == p.group.is_some() {
+            return Err(format!("{owner} needs one of user or group"));
+        }
+        let uid = p.user.as_deref().map(crate::server::uid).transpose();
+        let gid = p.group.as_deref().map(crate::server::gid).transpose();
+        let err = |e: std::io::Error| format!("{owner}: {e}");
+        known_channels(&owner, &p.channels, channels)?;
+
+        Ok(Self {
+            uid: uid.map_err(err)?,
+            gid: gid.map_err(err)?,
+            channels: p.channels.map(HashSet::from_iter),
+            ops: p.ops.map(HashSet::from_iter),
+            limit: p.limit,
+        })
+    }
+
+    pub fn bearer(&self, name: &str) -> Bearer {
+        Bearer {
+            kind: "peer",
+            name: name.to_string(),
+            channels: self.channels.clone(),
+            ops: self.ops.clone(),
+            limit: self.limit.clone(),
         }
     }
 }
 
+#[derive(Debug, Default)]
+/// unix socket peers whose forwarded headers are believed, the proxy in
+/// front of iris. anyone else on the socket could make them up
+pub struct TrustedPeers {
+    pub uids: HashSet<u32>,
+    /// only the primary group of the process is known
+    pub gids: HashSet<u32>,
+}
+
+impl TrustedPeers {
+    /// the `www-data` user nginx runs as when not set, skipped if there
+    /// is none. not its group, other services share that one
+    fn new(tp: Option<config_toml::TrustedPeers>) -> Result<Self, String> {
+        let Some(tp) = tp else {
+            let uids = crate::server::uid("www-data").into_iter().collect();
+            return Ok(Self { uids, gids: HashSet::new() });
+        };
+
+        let err = |e: std::io::Error| format!("trusted_peers: {e}");
+        Ok(Self {
+            uids: tp
+                .users
+                .iter()
+                .map(|v| crate::server::uid(v))
+                .collect::<Result<_, _>>()
+                .map_err(err)?,
+            gids: tp
+                .groups
+                .iter()
+                .map(|v| crate::server::gid(v))
+                .collect::<Result<_, _>>()
+                .map_err(err)?,
+        })
+    }
+
+    pub fn trusts(&self, cred: crate::server::PeerCred) -> bool {
+        self.uids.contains(&cred.uid) || self.gids.contains(&cred.gid)
+    }
+}
+
+static STATE: OnceLock<ArcSwap<Config>> = OnceLock::new();
+
 #[derive(Debug)]
 /// `Iris` Config
 pub struct Config {
     pub tc: reqwest::Client,
-    pub tb: String,
-    pub channels: HashMap<String, config_toml::Channel>,
-    pub send_message: reqwest::Url,
-    pub send_document: reqwest::Url,
+    pub channels: HashMap<String, Channel>,
+    pub tokens: HashMap<String, Token>,
+    /// local processes trusted by the uid or gid of their unix socket
+    pub peers: HashMap<String, Peer>,
+    /// how far the timestamp of a signed request may be from now, in seconds
+    pub hmac_skew: u64,
+    /// peers whose `X-Forwarded-For` and `X-Real-IP` headers are believed
+    pub trusted_proxies: Vec<IpNet>,
+    /// the same for unix socket peers, by uid or gid
+    pub trusted_peers: TrustedPeers,
+    /// `{tel_api}/bot{tel_token}/`, the base of every bot api method
+    pub tel_base: Secret<reqwest::Url>,
+    /// running against a local `telegram-bot-api` server
+    pub tel_local: bool,
+    /// bot api requests go through these when there are any
+    pub proxies: Vec<Proxy>,
+    /// files under this directory can be sent by their path
+    pub local_dir: Option<PathBuf>,
+    pub smtp: Option<crate::delivery::email::Smtp>,
+    /// the sqlite database, for admin api tokens and the audit log
+    pub db: PathBuf,
+    /// listeners and workers of the http server, read once at startup
+    pub server: crate::server::Server,
+    pub breaker: crate::breaker::Breaker,
+    /// where the log goes and how much of it, see [`crate::logger`]
+    pub log: crate::logger::Log,
 }
 
 impl Config {
     // pub const RMBGU: &str = "https://api.remove.bg/v1.0/removebg";
-    // pub const TOKEN_LIFE: i64 = 30 * 24 * 3600;
+    /// default lifetime of tokens made with the admin api, in seconds
+    pub const TOKEN_LIFE: i64 = 30 * 24 * 3600;
     pub const API_VERSION: &str = "0.1.0";
+    pub const TEL_API: &str = "https://api.telegram.org";
     // pub const RECORD_DIR: &str = "record";
 
     // pub const HTML_HEAD: &str = "./app/html/head.html";
     // pub const HTML_SCRIPTS: &str = "./app/dist/html/clean.html";
 
     // pub const CODE_ABC: &[u8] = b"0123456789";
-    // pub const TOKEN_ABC: &[u8] = b"!@#$%^&*_+abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789!@#$%^&*_+";
+    /// no symbols, tokens end up in headers and shell scripts
+    pub const TOKEN_ABC: &[u8] =
+        b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
     // pub const SALT_ABC: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
     // pub const USERNAME_ABC: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789_";
 
@@ -87,6 +610,34 @@ impl Config {
     // }
 
     fn tc_client() -> reqwest::Client {
+        Self::tc_builder().build().expect("could not build telegram client")
+    }
+
+    fn proxies(list: Vec<config_toml::Proxy>) -> Result<Vec<Proxy>, String> {
+        let mut proxies = Vec::<Proxy>::with_capacity(list.len());
+        for p in list {
+            let err = |e| format!("proxy {}: {e}", p.name);
+            if proxies.iter().any(|v| v.name == p.name) {
+                return Err(err("the name is used twice".to_string()));
+            }
+            // reqwest reads anything without a scheme as http
+            let url = reqwest::Url::from_str(p.url.expose())
+                .map_err(|e| err(format!("bad url: {e}")))?;
+            if !["http", "https", "socks5", "socks5h"].contains(&url.scheme()) {
+                return Err(err(format!("{} is not supported", url.scheme())));
+            }
+            let proxy =
+                reqwest::Proxy::all(url).map_err(|e| err(e.to_string()))?;
+            let client = Self::tc_builder()
+                .proxy(proxy)
+                .build()
+                .map_err(|e| err(e.to_string()))?;
+            proxies.push(Proxy { name: p.name, client });
+        }
+        Ok(proxies)
+    }
+
+    fn tc_builder() -> reqwest::ClientBuilder {
         // use reqwest::header as hh;
         // let mut hm = hh::HeaderMap::new();
         // let av = hh::HeaderValue::from_str(token).expect("invalid hc token");
@@ -95,35 +646,260 @@ impl Config {
         reqwest::ClientBuilder::new()
             // .default_headers(hm)
             .timeout(std::time::Duration::from_secs(500))
+            // big uploads need the long timeout, a dead host should not
+            .connect_timeout(Duration::from_secs(10))
             .connection_verbose(false)
-            .build()
-            .expect("could not build telegram client")
     }
 
     fn init() -> Self {
-        let ct = config_toml::get();
+        match Self::load() {
+            Ok(v) => v,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    /// read and check the config file without putting it in use
+    pub fn load() -> Result<Self, String> {
+        Self::new(config_toml::get()?)
+    }
+
+    #[cfg(test)]
+    /// a config from toml source, checked but not put in use
+    pub fn parse(data: &str) -> Result<Self, String> {
+        Self::new(config_toml::parse(data, "<string>".as_ref())?)
+    }
+
+    #[cfg(test)]
+    /// build a config from toml source, `get` must not have been called yet
+    pub fn set(data: &str) {
+        let conf = Self::parse(data).expect("invalid test config");
+        if STATE.set(ArcSwap::from_pointee(conf)).is_err() {
+            panic!("config is already set");
+        }
+    }
 
+    fn new(ct: config_toml::ConfigToml) -> Result<Self, String> {
         Self::create_dirs().expect("failed to create required directories");
 
-        Self {
+        let smtp =
+            ct.smtp.map(crate::delivery::email::Smtp::new).transpose()?;
+        let proxies = Self::proxies(ct.proxies)?;
+        let mut sources = ct.sources;
+        let channels: HashMap<String, Channel> = ct
+            .channels
+            .into_iter()
+            .map(|(k, v)| {
+                let source = sources.remove(&k).unwrap_or_default();
+                let ch = Channel::new(&k, v, smtp.is_some(), &proxies, source)?;
+                Ok((k, ch))
+            })
+            .collect::<Result<_, String>>()?;
+
+        for (name, ch) in &channels {
+            let Some(fb) = &ch.fallback else { continue };
+            if fb == name || !channels.contains_key(fb) {
+                return Err(format!("channel {name}: bad fallback {fb}"));
+            }
+        }
+
+        let tokens = ct
+            .tokens
+            .into_iter()
+            .map(|(k, v)| {
+                let t = Token::new(&k, v, &channels)?;
+                Ok((k, t))
+            })
+            .collect::<Result<HashMap<_, _>, String>>()?;
+        // a bearer token must not fit two prefixes, or one hash check would
+        // not be enough to tell who it is
+        for (a, ta) in &tokens {
+            for (b, tb) in &tokens {
+                if let (Some(pa), Some(pb)) = (&ta.prefix, &tb.prefix)
+                    && a != b
+                    && pb.starts_with(pa.as_str())
+                {
+                    return Err(format!(
+                        "token {b}: prefix overlaps token {a}"
+                    ));
+                }
+            }
+        }
+
+        let peers = ct
+            .peers
+            .into_iter()
+            .map(|(k, v)| {
+                let p = Peer::new(&k, v, &channels)?;
+                Ok((k, p))
+            })
+            .collect::<Result<_, String>>()?;
+
+        let tel_api = ct.tel_api.as_deref().unwrap_or(Self::TEL_API);
+        let tel_base = format!(
+            "{}/bot{}/",
+            tel_api.trim_end_matches('/'),
+            ct.tel_token.expose()
+        );
+        let tel_base = match reqwest::Url::from_str(&tel_base) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("invalid tel_api {tel_api}: {e}")),
+        };
+
+        let local_dir = ct
+            .local_dir
+            .map(|v| {
+                v.canonicalize()
+                    .map_err(|e| format!("invalid local_dir {v:?}: {e}"))
+            })
+            .transpose()?;
+
+        let trusted_proxies = nets(
+            "trusted_proxies",
+            ct.trusted_proxies.unwrap_or_else(|| {
+                vec!["127.0.0.0/8".to_string(), "::1/128".to_string()]
+            }),
+        )?;
+
+        let server = ct.server.unwrap_or_default();
+        server.check()?;
+        let breaker = ct.breaker.unwrap_or_default();
+        breaker.check()?;
+        let log = ct.log.unwrap_or_default();
+        log.check()?;
+
+        Ok(Self {
             tc: Self::tc_client(),
-            channels: ct.channels,
-            send_message: reqwest::Url::from_str(&format!(
-                "https://api.telegram.org/bot{}/sendMessage",
-                ct.tel_token
-            ))
-            .expect("url err"),
-            send_document: reqwest::Url::from_str(&format!(
-                "https://api.telegram.org/bot{}/sendDocument",
-                ct.tel_token
-            ))
-            .expect("url err"),
-            tb: ct.tel_token,
-        }
-    }
-
-    pub fn get() -> &'static Self {
-        static STATE: OnceLock<Config> = OnceLock::new();
-        STATE.get_or_init(Self::init)
+            channels,
+            tokens,
+            peers,
+            hmac_skew: ct.hmac_skew.unwrap_or(300),
+            trusted_proxies,
+            trusted_peers: TrustedPeers::new(ct.trusted_peers)?,
+            tel_base: Secret::new(tel_base),
+            tel_local: ct.tel_local,
+            proxies,
+            local_dir,
+            smtp,
+            db: ct.db.unwrap_or_else(|| PathBuf::from("main.db")),
+            server,
+            breaker,
+            log,
+        })
+    }
+
+    /// the peer entry for a unix socket process, a user entry wins over
+    /// a group one
+    pub fn peer(&self, cred: crate::server::PeerCred) -> Option<Bearer> {
+        let mut names = self.peers.keys().collect::<Vec<_>>();
+        names.sort();
+        let user = names.iter().find(|v| self.peers[**v].uid == Some(cred.uid));
+        let group =
+            || names.iter().find(|v| self.peers[**v].gid == Some(cred.gid));
+        let name = user.or_else(group)?;
+        Some(self.peers[*name].bearer(name))
+    }
+
+    /// url of a bot api method e.g. `sendMessage`
+    pub fn tel_method(&self, method: &str) -> reqwest::Url {
+        self.tel_base.expose().join(method).expect("invalid telegram method")
+    }
+
+    /// biggest file the bot api accepts, a local server allows up to 2GB
+    pub fn max_file_size(&self) -> u64 {
+        if self.tel_local { 2_000_000_000 } else { 50_000_000 }
+    }
+
+    /// the config in use, requests hold on to the one they started with
+    /// so a reload does not change it under their feet
+    pub fn get() -> Arc<Self> {
+        STATE.get_or_init(|| ArcSwap::from_pointee(Self::init())).load_full()
+    }
+
+    /// read the config file again and put it in use, on any error the
+    /// old config stays
+    pub fn reload() -> Result<(), String> {
+        let new = Self::load()?;
+        let old = Self::get();
+
+        log::info!("[config] reloaded, channels {}", Self::diff(&old, &new));
+        if old.db != new.db {
+            log::warn!("[config] db changed, it takes effect after a restart");
+        }
+        if old.server != new.server {
+            log::warn!(
+                "[config] server changed, it takes effect after a restart"
+            );
+        }
+
+        if old.log != new.log {
+            crate::logger::configure(&new.log);
+        }
+
+        STATE
+            .get_or_init(|| ArcSwap::from_pointee(Self::init()))
+            .store(Arc::new(new));
+        Ok(())
+    }
+
+    /// which channels were added, removed or changed from `old` to `new`
+    pub fn diff(old: &Self, new: &Self) -> String {
+        let mut added = Vec::new();
+        let mut changed = Vec::new();
+        for (name, ch) in &new.channels {
+            match old.channels.get(name) {
+                None => added.push(name.as_str()),
+                Some(v) if v.source != ch.source => changed.push(name.as_str()),
+                Some(_) => {}
+            }
+        }
+        let mut removed = old
+            .channels
+            .keys()
+            .filter(|v| !new.channels.contains_key(*v))
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+
+        added.sort_unstable();
+        changed.sort_unstable();
+        removed.sort_unstable();
+        format!("added: {added:?}, removed: {removed:?}, changed: {changed:?}")
+    }
+
+    /// reload the config on SIGHUP and whenever its file is modified
+    pub fn watch() {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let path = config_toml::path();
+        let modified = move || std::fs::metadata(&path)?.modified();
+
+        tokio::spawn(async move {
+            let mut hup = match signal(SignalKind::hangup()) {
+                Ok(v) => v,
+                Err(e) => {
+                    log::error!("[config] can not listen for SIGHUP: {e}");
+                    return;
+                }
+            };
+            let mut tick = tokio::time::interval(Duration::from_secs(2));
+            let mut last = modified().ok();
+
+            loop {
+                tokio::select! {
+                    _ = hup.recv() => log::info!("[config] got SIGHUP"),
+                    _ = tick.tick() => {
+                        let now = modified().ok();
+                        if now == last {
+                            continue;
+                        }
+                        last = now;
+                        log::info!("[config] the file was modified");
+                    }
+                }
+
+                if let Err(e) = Self::reload() {
+                    log::error!("[config] reload failed, old one kept: {e}");
+                }
+            }
+        });
     }
 }
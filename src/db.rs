@@ -0,0 +1,22 @@
+//! the sqlite database, for api tokens and the audit log
+
+use sqlx::SqlitePool;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode};
+use std::path::Path;
+
+pub async fn connect(path: &Path) -> Result<SqlitePool, sqlx::Error> {
+    let cpt = SqliteConnectOptions::new()
+        .filename(path)
+        .create_if_missing(true)
+        .journal_mode(SqliteJournalMode::Wal);
+
+    let pool = SqlitePool::connect_with(cpt).await?;
+    migrate(&pool).await?;
+    Ok(pool)
+}
+
+/// bring the schema up to date with `migrations/`
+pub async fn migrate(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    sqlx::migrate!().run(pool).await?;
+    Ok(())
+}
@@ -0,0 +1,216 @@
+use super::{Message, ParseMode, Severity, Source};
+use crate::config::{Config, SmtpTls, SmtpToml};
+use crate::models::AppErr;
+use lettre::message::{Attachment, Mailbox, MultiPart, SinglePart, header};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+
+/// the smtp relay that email channels are delivered through
+pub struct Smtp {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    /// the circuit breaker of the relay, `smtp:host:port`
+    pub breaker: String,
+    /// the biggest attachment, in bytes
+    pub max_attachment: u64,
+}
+
+impl Smtp {
+    /// what most relays take, the mail grows by a third with base64
+    const MAX_ATTACHMENT: u64 = 18_000_000;
+
+    pub fn new(st: SmtpToml) -> Result<Self, String> {
+        type T = AsyncSmtpTransport<Tokio1Executor>;
+        let builder = match st.tls {
+            SmtpTls::None => Ok(T::builder_dangerous(&st.host)),
+            SmtpTls::Starttls => T::starttls_relay(&st.host),
+            SmtpTls::Tls => T::relay(&st.host),
+        };
+        let mut builder = match builder {
+            Ok(v) => v,
+            Err(e) => {
+                return Err(format!("invalid smtp host {}: {e}", st.host));
+            }
+        };
+
+        let port = st.port.unwrap_or(match st.tls {
+            SmtpTls::None => 25,
+            SmtpTls::Starttls => 587,
+            SmtpTls::Tls => 465,
+        });
+        builder = builder.port(port);
+        let breaker = format!("smtp:{}:{port}", st.host);
+        if let Some(user) = st.user {
+            let pass = st.pass.map(|v| v.expose().clone()).unwrap_or_default();
+            builder = builder.credentials(Credentials::new(user, pass));
+        }
+
+        let from = match st.from.parse() {
+            Ok(v) => v,
+            Err(e) => {
+                return Err(format!(
+                    "invalid smtp from address {}: {e}",
+                    st.from
+                ));
+            }
+        };
+
+        let max_attachment = st.max_attachment.unwrap_or(Self::MAX_ATTACHMENT);
+        Ok(Self { transport: builder.build(), from, breaker, max_attachment })
+    }
+}
+
+// the transport would print the smtp credentials
+impl std::fmt::Debug for Smtp {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Smtp").field("from", &self.from).finish_non_exhaustive()
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// rough plain text version of a telegram flavored html message
+pub fn strip_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut in_tag = false;
+    for c in text.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => out.push(c),
+            _ => {}
+        }
+    }
+
+    out.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&amp;", "&")
+}
+
+/// the text as plain and html alternatives
+pub fn body(msg: &Message<'_>) -> MultiPart {
+    let (plain, html) = match msg.parse_mode {
+        Some(ParseMode::Html) => (strip_html(msg.text), msg.text.to_string()),
+        _ => (
+            msg.text.to_string(),
+            format!(
+                "<pre style=\"white-space: pre-wrap\">{}</pre>",
+                escape_html(msg.text)
+            ),
+        ),
+    };
+
+    MultiPart::alternative_plain_html(
+        plain,
+        format!("<!doctype html><html><body>{html}</body></html>"),
+    )
+}
+
+/// the configured subject or the first line of the message, after the
+/// severity when it has one
+pub fn subject(subject: Option<&str>, msg: &Message<'_>) -> String {
+    let subject = match subject {
+        Some(s) => s.to_string(),
+        None => first_line(msg),
+    };
+    let severity = match msg.severity {
+        None => return subject,
+        Some(Severity::Debug) => "debug",
+        Some(Severity::Info) => "info",
+        Some(Severity::Warning) => "warning",
+        Some(Severity::Error) => "error",
+        Some(Severity::Critical) => "critical",
+    };
+    format!("[{severity}] {subject}")
+}
+
+fn first_line(msg: &Message<'_>) -> String {
+    let line = msg.text.lines().next().unwrap_or_default();
+    let line = match msg.parse_mode {
+        Some(ParseMode::Html) => strip_html(line),
+        _ => line.to_string(),
+    };
+
+    let line: String = line.trim().chars().take(78).collect();
+    if line.is_empty() { String::from("iris") } else { line }
+}
+
+pub async fn send(
+    to: &[Mailbox], subj: Option<&str>, mut msg: Message<'_>,
+) -> Result<(), AppErr> {
+    let conf = Config::get();
+    let Some(smtp) = &conf.smtp else {
+        return crate::err!(ServerError, "smtp is not configured");
+    };
+
+    let mut builder = lettre::Message::builder()
+        .from(smtp.from.clone())
+        .subject(subject(subj, &msg));
+    for m in to {
+        builder = builder.to(m.clone());
+    }
+
+    let body = match msg.document.take() {
+        None => body(&msg),
+        Some(doc) => {
+            // it is all held in memory, so the size is checked first
+            let max = smtp.max_attachment;
+            let data = match doc.source {
+                Source::Local(path) => {
+                    if tokio::fs::metadata(path).await?.len() >= max {
+                        return crate::err!(
+                            FileTooBig,
+                            format!("max attachment size is {max} bytes")
+                        );
+                    }
+                    tokio::fs::read(path).await?
+                }
+                Source::Upload(up) => up.collect(max).await?,
+            };
+            let mime =
+                doc.mime.as_deref().unwrap_or("application/octet-stream");
+            let ct = header::ContentType::parse(mime).unwrap_or_else(|_| {
+                header::ContentType::parse("application/octet-stream")
+                    .expect("octet-stream is a valid mime")
+            });
+            let name = doc.name.unwrap_or("file").to_string();
+            let att: SinglePart = Attachment::new(name).body(data, ct);
+            MultiPart::mixed().multipart(body(&msg)).singlepart(att)
+        }
+    };
+
+    let email = match builder.multipart(body) {
+        Ok(v) => v,
+        Err(e) => {
+            log::error!("[email_err]: {e:#?}");
+            return crate::err!(SendFailed, "building the email failed");
+        }
+    };
+
+    let r = smtp.transport.send(email).await;
+    // a refused message or a bug on our side still means the relay is up
+    let up = r
+        .as_ref()
+        .err()
+        .is_none_or(|e| e.is_permanent() || e.is_client() || e.is_response());
+    crate::breaker::record(&conf, &smtp.breaker, up);
+    if let Err(e) = r {
+        log::error!("[smtp_err]: {e:#?}");
+        return crate::err!(SendFailed, "sending email failed");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,192 @@
+use crate::breaker::Probe;
+use crate::config::{Channel, Config, Target};
+use crate::metrics::InFlight;
+use crate::models::AppErr;
+use actix_web::web::Bytes;
+use std::borrow::Cow;
+use std::path::Path;
+use tokio::sync::mpsc;
+
+pub mod email;
+mod push;
+pub mod telegram;
+
+#[derive(Debug, serde::Deserialize, utoipa::ToSchema, Clone, Copy)]
+pub enum ParseMode {
+    Markdown,
+    MarkdownV2,
+    Html,
+}
+
+impl ParseMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::MarkdownV2 => "MarkdownV2",
+            Self::Markdown => "Markdown",
+            Self::Html => "HTML",
+        }
+    }
+}
+
+#[derive(Debug, serde::Deserialize, utoipa::ToSchema, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+/// how urgent a message is, used by the push backends as the priority
+pub enum Severity {
+    Debug,
+    Info,
+    Warning,
+    Error,
+    Critical,
+}
+
+/// chunks of a file that is still being uploaded to iris
+pub struct Upload(mpsc::Receiver<std::io::Result<Bytes>>);
+
+impl Upload {
+    /// the upload and the sender that feeds it, the channel is bounded
+    /// so a slow backend slows down the client instead of eating memory
+    pub fn new() -> (mpsc::Sender<std::io::Result<Bytes>>, Self) {
+        let (tx, rx) = mpsc::channel(8);
+        (tx, Self(rx))
+    }
+
+    pub fn into_body(self) -> reqwest::Body {
+        let stream = futures_util::stream::unfold(self.0, |mut rx| async {
+            let chunk = rx.recv().await?;
+            Some((chunk, rx))
+        });
+        reqwest::Body::wrap_stream(stream)
+    }
+
+    /// for the backends that need the whole file at once, gives up as
+    /// soon as it reaches `max` bytes
+    pub async fn collect(mut self, max: u64) -> Result<Vec<u8>, AppErr> {
+        let mut data = Vec::new();
+        while let Some(chunk) = self.0.recv().await {
+            data.extend_from_slice(&chunk?);
+            if data.len() as u64 >= max {
+                return crate::err!(
+                    FileTooBig,
+                    format!("max attachment size is {max} bytes")
+                );
+            }
+        }
+        Ok(data)
+    }
+}
+
+pub enum Source<'a> {
+    /// a file under `local_dir` that outlives the request,
+    /// so a local bot api server can read it directly
+    Local(&'a Path),
+    Upload(Upload),
+}
+
+/// a file sent alongside the message
+pub struct Document<'a> {
+    pub name: Option<&'a str>,
+    pub mime: Option<String>,
+    pub source: Source<'a>,
+}
+
+pub struct Message<'a> {
+    pub text: &'a str,
+    pub parse_mode: Option<ParseMode>,
+    pub severity: Option<Severity>,
+    pub document: Option<Document<'a>>,
+}
+
+/// deliver the message to wherever the channel points at,
+/// gives back the message id for telegram channels. while its backend is
+/// down the message goes to the fallback channel, if there is one
+pub async fn send(
+    ch: &Channel, msg: Message<'_>,
+) -> Result<Option<i64>, AppErr> {
+    let conf = Config::get();
+    let e = match admit(&conf, ch) {
+        Ok(_probe) => return deliver(ch, msg).await,
+        Err(e) => e,
+    };
+    let Some((name, fb)) =
+        ch.fallback.as_ref().and_then(|v| Some((v, conf.channels.get(v)?)))
+    else {
+        return Err(e);
+    };
+
+    // one hop only, a fallback that is down as well fails
+    let _probe = admit(&conf, fb)?;
+    // the rules of the channel hold on the way to its fallback too
+    let text = redact(ch, msg.text)?;
+    log::warn!("[breaker] {}, sent to the fallback {name}", e.reason());
+    deliver(fb, Message { text: &text, ..msg }).await
+}
+
+/// fail fast while the backend of the channel is down
+fn admit(conf: &Config, ch: &Channel) -> Result<Probe, AppErr> {
+    match ch.target.breaker(conf) {
+        Some(key) => crate::breaker::admit(&key),
+        None => Ok(Probe::default()),
+    }
+}
+
+async fn deliver(
+    ch: &Channel, msg: Message<'_>,
+) -> Result<Option<i64>, AppErr> {
+    let backend = ch.target.kind();
+    let _in_flight =
+        InFlight::new("iris_deliveries_in_flight", &[("backend", backend)]);
+    let text = redact(ch, msg.text)?;
+    let msg = Message { text: &text, ..msg };
+
+    match &ch.target {
+        Target::Telegram { chat, thread, proxies } => {
+            let via = proxies.as_deref();
+            telegram::send(chat, thread.as_deref(), via, msg).await.map(Some)
+        }
+        Target::Email { to, subject } => {
+            email::send(to, subject.as_deref(), msg).await.map(|_| None)
+        }
+        Target::Ntfy { url, token, title, tags } => {
+            let token = token.as_ref().map(|v| v.expose().as_str());
+            let title = title.as_deref();
+            push::ntfy(url, token, title, tags, msg).await.map(|_| None)
+        }
+        Target::Gotify { url, token } => {
+            push::gotify(url, token.expose(), msg).await.map(|_| None)
+        }
+    }
+}
+
+/// replace the text of a sent message, only telegram can do that
+pub async fn edit(
+    ch: &Channel, message_id: i64, text: &str, parse_mode: Option<ParseMode>,
+) -> Result<(), AppErr> {
+    let _probe = admit(&Config::get(), ch)?;
+    let text = redact(ch, text)?;
+    match &ch.target {
+        Target::Telegram { chat, proxies, .. } => {
+            let via = proxies.as_deref();
+            telegram::edit(chat, via, message_id, &text, parse_mode).await
+        }
+        _ => crate::err!(Unsupported, "only telegram messages can be edited"),
+    }
+}
+
+/// the text with the secrets masked, the caption of files included
+fn redact<'a>(ch: &Channel, text: &'a str) -> Result<Cow<'a, str>, AppErr> {
+    match &ch.redact {
+        Some(r) => r.apply(text),
+        None => Ok(Cow::Borrowed(text)),
+    }
+}
+
+/// remove a sent message, only telegram can do that
+pub async fn delete(ch: &Channel, message_id: i64) -> Result<(), AppErr> {
+    let _probe = admit(&Config::get(), ch)?;
+    match &ch.target {
+        Target::Telegram { chat, proxies, .. } => {
+            telegram::delete(chat, proxies.as_deref(), message_id).await
+        }
+        _ => crate::err!(Unsupported, "only telegram messages can be deleted"),
+    }
+}
@@ -0,0 +1,151 @@
+use super::{Message, ParseMode, Severity, Source};
+use crate::breaker;
+use crate::config::Config;
+use crate::models::AppErr;
+
+/// ntfy priorities go from 1 (min) to 5 (max)
+fn ntfy_priority(severity: Option<Severity>) -> u8 {
+    match severity {
+        Some(Severity::Debug) => 1,
+        Some(Severity::Info) => 2,
+        None | Some(Severity::Warning) => 3,
+        Some(Severity::Error) => 4,
+        Some(Severity::Critical) => 5,
+    }
+}
+
+/// gotify priorities go from 0 (silent) to 10 (max)
+fn gotify_priority(severity: Option<Severity>) -> u8 {
+    match severity {
+        Some(Severity::Debug) => 1,
+        Some(Severity::Info) => 4,
+        None | Some(Severity::Warning) => 5,
+        Some(Severity::Error) => 8,
+        Some(Severity::Critical) => 10,
+    }
+}
+
+/// tell the breaker how the request went
+fn sent(
+    conf: &Config, breaker: String, r: reqwest::Result<reqwest::Response>,
+) -> reqwest::Result<reqwest::Response> {
+    breaker::outcome(conf, &breaker, &r);
+    r
+}
+
+/// a header value as is when it is plain ascii, otherwise as an rfc 2047
+/// encoded word, which ntfy decodes
+fn ntfy_header(value: &str) -> String {
+    if value.bytes().all(|b| b.is_ascii_graphic() || b == b' ') {
+        return value.to_string();
+    }
+    let mut out = String::from("=?UTF-8?Q?");
+    for b in value.bytes() {
+        match b {
+            b' ' => out.push('_'),
+            b if b.is_ascii_alphanumeric() => out.push(b as char),
+            b => out.push_str(&format!("={b:02X}")),
+        }
+    }
+    out.push_str("?=");
+    out
+}
+
+pub async fn ntfy(
+    url: &reqwest::Url, token: Option<&str>, title: Option<&str>,
+    tags: &[String], msg: Message<'_>,
+) -> Result<(), AppErr> {
+    let conf = Config::get();
+    let priority = ntfy_priority(msg.severity).to_string();
+    let breaker = breaker::key("ntfy", url);
+
+    // the text is the body, unless there is a file to send as the body,
+    // then it goes in a header. never in the url, proxies log those
+    let mut rq = match msg.document {
+        None => conf.tc.post(url.clone()).body(msg.text.to_string()),
+        Some(doc) => {
+            let body = match doc.source {
+                Source::Local(path) => {
+                    tokio::fs::File::open(path).await?.into()
+                }
+                Source::Upload(up) => up.into_body(),
+            };
+            let mut rq = conf.tc.put(url.clone()).body(body);
+            if !msg.text.is_empty() {
+                rq = rq.header("X-Message", ntfy_header(msg.text));
+            }
+            if let Some(name) = doc.name {
+                rq = rq.header("X-Filename", ntfy_header(name));
+            }
+            rq
+        }
+    };
+
+    rq = rq.header("X-Priority", priority);
+    if matches!(
+        msg.parse_mode,
+        Some(ParseMode::Markdown | ParseMode::MarkdownV2)
+    ) {
+        rq = rq.header("X-Markdown", "yes");
+    }
+    if let Some(title) = title {
+        rq = rq.header("X-Title", ntfy_header(title));
+    }
+    if !tags.is_empty() {
+        rq = rq.header("X-Tags", ntfy_header(&tags.join(",")));
+    }
+
+    if let Some(token) = token {
+        rq = rq.bearer_auth(token);
+    }
+
+    let r = sent(&conf, breaker, rq.send().await)?;
+    if !r.status().is_success() {
+        log::error!("[ntfy_err]: {:#?}", r.text().await);
+        return crate::err!(SendFailed, "sending push to ntfy failed");
+    }
+
+    Ok(())
+}
+
+#[derive(serde::Serialize)]
+struct GotifyBody<'a> {
+    message: &'a str,
+    priority: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extras: Option<serde_json::Value>,
+}
+
+pub async fn gotify(
+    url: &reqwest::Url, token: &str, msg: Message<'_>,
+) -> Result<(), AppErr> {
+    if msg.document.is_some() {
+        return crate::err!(Unsupported, "gotify does not support files");
+    }
+
+    let conf = Config::get();
+    let extras = match msg.parse_mode {
+        Some(ParseMode::Markdown | ParseMode::MarkdownV2) => {
+            Some(serde_json::json!({
+                "client::display": { "contentType": "text/markdown" }
+            }))
+        }
+        _ => None,
+    };
+
+    let bd = GotifyBody {
+        message: msg.text,
+        priority: gotify_priority(msg.severity),
+        extras,
+    };
+
+    let rq = conf.tc.post(url.clone()).header("X-Gotify-Key", token);
+    let r =
+        sent(&conf, breaker::key("gotify", url), rq.json(&bd).send().await)?;
+    if !r.status().is_success() {
+        log::error!("[gotify_err]: {:#?}", r.text().await);
+        return crate::err!(SendFailed, "sending push to gotify failed");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,320 @@
+use super::{Message, ParseMode, Source};
+use crate::config::Config;
+use crate::metrics;
+use crate::models::AppErr;
+use reqwest::multipart::Part;
+
+#[derive(serde::Serialize)]
+struct LinkPreviewOptions {
+    is_disabled: bool,
+    prefer_small_media: bool,
+}
+
+impl Default for LinkPreviewOptions {
+    fn default() -> Self {
+        Self { is_disabled: false, prefer_small_media: true }
+    }
+}
+
+#[derive(serde::Serialize)]
+struct SendMessageBody<'a> {
+    chat_id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message_thread_id: Option<&'a str>,
+    text: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parse_mode: Option<&'static str>,
+    link_preview_options: LinkPreviewOptions,
+}
+
+#[derive(serde::Deserialize)]
+struct TelMessage {
+    message_id: i64,
+}
+
+#[derive(serde::Deserialize)]
+struct TelResponse<T> {
+    result: T,
+}
+
+#[derive(serde::Deserialize)]
+struct TelErrorParams {
+    retry_after: Option<u64>,
+}
+
+#[derive(serde::Deserialize)]
+struct TelError {
+    description: Option<String>,
+    parameters: Option<TelErrorParams>,
+}
+
+/// the proxies of the channel, see [`crate::proxy::send`]
+pub type Via<'a> = Option<&'a [String]>;
+
+/// send a bot api request and read its `result`
+async fn call<T: serde::de::DeserializeOwned>(
+    conf: &Config, via: Via<'_>, rq: reqwest::RequestBuilder,
+    what: &'static str,
+) -> Result<T, AppErr> {
+    let rq = rq.build()?;
+    // the last part of the path, the rest holds the token
+    let method = rq.url().path().rsplit('/').next().unwrap_or_default();
+    let method = method.to_string();
+    let breaker = crate::breaker::key("telegram", conf.tel_base.expose());
+
+    let started = std::time::Instant::now();
+    let r = crate::proxy::send(conf, via, rq).await;
+    let took = started.elapsed();
+    metrics::observe(
+        "iris_telegram_duration_seconds",
+        &[("method", &method)],
+        took,
+    );
+
+    let status = r.as_ref().map_or(0, |v| v.status().as_u16());
+    if status != 200 {
+        let l = [("method", method.as_str()), ("status", &status.to_string())];
+        metrics::add("iris_telegram_errors_total", &l, 1.0);
+    }
+    crate::breaker::outcome(conf, &breaker, &r);
+    let r = r?;
+    if r.status() == 429 {
+        // hammering on would only get the bot banned, pass the wait along
+        let body = r.json::<TelError>().await.ok();
+        let wait = body.and_then(|v| v.parameters?.retry_after).unwrap_or(1);
+        metrics::rate_limited("telegram", Some(wait));
+        log::warn!("[tel_err]: rate limited for {wait}s");
+        return Err(crate::err!(r, RateLimited, "telegram rate limit")
+            .retry_after(wait));
+    }
+    if r.status() != 200 {
+        let body = r.text().await.unwrap_or_default();
+        log::error!("[tel_err]: {body}");
+        let desc = serde_json::from_str::<TelError>(&body)
+            .ok()
+            .and_then(|v| v.description)
+            .unwrap_or_default();
+        return crate::err!(
+            SendFailed,
+            format!("{what} telegram failed: {desc}")
+        );
+    }
+
+    match r.json::<TelResponse<T>>().await {
+        Ok(v) => Ok(v.result),
+        Err(e) => {
+            log::error!("[tel_err]: bad response: {e:#?}");
+            crate::err!(SendFailed, "bad response from telegram")
+        }
+    }
+}
+
+pub async fn send(
+    chat: &str, thread: Option<&str>, via: Via<'_>, msg: Message<'_>,
+) -> Result<i64, AppErr> {
+    let conf = Config::get();
+
+    let Some(doc) = msg.document else {
+        let bd = SendMessageBody {
+            chat_id: chat,
+            message_thread_id: thread,
+            text: msg.text,
+            parse_mode: msg.parse_mode.map(|v| v.as_str()),
+            link_preview_options: Default::default(),
+        };
+
+        let url = conf.tel_method("sendMessage");
+        let rq = conf.tc.post(url).json(&bd);
+        let m: TelMessage = call(&conf, via, rq, "sending message to").await?;
+        return Ok(m.message_id);
+    };
+
+    let mut sf = reqwest::multipart::Form::new()
+        .text("chat_id", chat.to_string())
+        .text("caption", msg.text.to_string());
+
+    if let Some(pm) = &msg.parse_mode {
+        sf = sf.text("parse_mode", pm.as_str());
+    }
+
+    if let Some(tid) = thread {
+        sf = sf.text("message_thread_id", tid.to_string());
+    }
+
+    // the document goes last, so everything else is already in place
+    // by the time telegram starts receiving a long upload
+    sf = match doc.source {
+        Source::Local(path) if conf.tel_local => {
+            sf.text("document", format!("file://{}", path.display()))
+        }
+        source => {
+            let mut part = match source {
+                Source::Local(path) => Part::file(path).await?,
+                Source::Upload(up) => Part::stream(up.into_body()),
+            };
+            if let Some(fname) = doc.name {
+                part = part.file_name(fname.to_string());
+            }
+            if let Some(mime) = &doc.mime {
+                part = part.mime_str(mime)?;
+            }
+            sf.part("document", part)
+        }
+    };
+
+    let url = conf.tel_method("sendDocument");
+    let rq = conf.tc.post(url).multipart(sf);
+    let m: TelMessage = call(&conf, via, rq, "sending file to").await?;
+    Ok(m.message_id)
+}
+
+#[derive(serde::Serialize)]
+struct EditMessageBody<'a> {
+    chat_id: &'a str,
+    message_id: i64,
+    text: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parse_mode: Option<&'static str>,
+    link_preview_options: LinkPreviewOptions,
+}
+
+pub async fn edit(
+    chat: &str, via: Via<'_>, message_id: i64, text: &str,
+    parse_mode: Option<ParseMode>,
+) -> Result<(), AppErr> {
+    let conf = Config::get();
+    let bd = EditMessageBody {
+        chat_id: chat,
+        message_id,
+        text,
+        parse_mode: parse_mode.map(|v| v.as_str()),
+        link_preview_options: Default::default(),
+    };
+
+    let url = conf.tel_method("editMessageText");
+    // the result is the edited message or `true` for inline messages
+    let rq = conf.tc.post(url).json(&bd);
+    let _: serde_json::Value =
+        call(&conf, via, rq, "editing message in").await?;
+    Ok(())
+}
+
+#[derive(serde::Serialize)]
+struct DeleteMessageBody<'a> {
+    chat_id: &'a str,
+    message_id: i64,
+}
+
+pub async fn delete(
+    chat: &str, via: Via<'_>, message_id: i64,
+) -> Result<(), AppErr> {
+    let conf = Config::get();
+    let bd = DeleteMessageBody { chat_id: chat, message_id };
+    let url = conf.tel_method("deleteMessage");
+    let rq = conf.tc.post(url).json(&bd);
+    let _: bool = call(&conf, via, rq, "deleting message in").await?;
+    Ok(())
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct TelUser {
+    pub id: i64,
+    pub username: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct TelChat {
+    /// private, group, supergroup or channel
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub title: Option<String>,
+    pub username: Option<String>,
+    /// topics are turned on, only then `message_thread_id` means anything
+    #[serde(default)]
+    pub is_forum: bool,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct TelChatMember {
+    /// creator, administrator, member, restricted, left or kicked
+    pub status: String,
+    pub can_post_messages: Option<bool>,
+    pub can_send_messages: Option<bool>,
+}
+
+impl TelChatMember {
+    /// may a member like this send messages to a chat of this kind
+    pub fn can_post(&self, kind: &str) -> bool {
+        match self.status.as_str() {
+            "creator" => true,
+            "administrator" if kind == "channel" => {
+                self.can_post_messages.unwrap_or(false)
+            }
+            "administrator" => true,
+            "member" => kind != "channel",
+            "restricted" => self.can_send_messages.unwrap_or(false),
+            _ => false,
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+struct ChatBody<'a> {
+    chat_id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message_thread_id: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    action: Option<&'a str>,
+}
+
+// the calls below take the config to check instead of the one in use
+
+pub async fn get_me(conf: &Config) -> Result<TelUser, AppErr> {
+    let url = conf.tel_method("getMe");
+    call(conf, None, conf.tc.post(url), "getting the bot from").await
+}
+
+pub async fn get_chat(
+    conf: &Config, via: Via<'_>, chat: &str,
+) -> Result<TelChat, AppErr> {
+    let bd = ChatBody {
+        chat_id: chat,
+        user_id: None,
+        message_thread_id: None,
+        action: None,
+    };
+    let url = conf.tel_method("getChat");
+    call(conf, via, conf.tc.post(url).json(&bd), "getting chat from").await
+}
+
+pub async fn get_chat_member(
+    conf: &Config, via: Via<'_>, chat: &str, user_id: i64,
+) -> Result<TelChatMember, AppErr> {
+    let bd = ChatBody {
+        chat_id: chat,
+        user_id: Some(user_id),
+        message_thread_id: None,
+        action: None,
+    };
+    let url = conf.tel_method("getChatMember");
+    let rq = conf.tc.post(url).json(&bd);
+    call(conf, via, rq, "getting chat member from").await
+}
+
+/// a typing action, the cheapest way to see that a thread exists
+pub async fn send_typing(
+    conf: &Config, via: Via<'_>, chat: &str, thread: &str,
+) -> Result<(), AppErr> {
+    let bd = ChatBody {
+        chat_id: chat,
+        user_id: None,
+        message_thread_id: Some(thread),
+        action: Some("typing"),
+    };
+    let url = conf.tel_method("sendChatAction");
+    let rq = conf.tc.post(url).json(&bd);
+    let _: bool = call(conf, via, rq, "sending action to").await?;
+    Ok(())
+}
@@ -10,7 +10,7 @@ use utoipa::{
     Modify,
     openapi::{
         self as oa, Components, Content, RefOr, Response, SecurityRequirement,
-        security::{ApiKey, ApiKeyValue, SecurityScheme},
+        security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
     },
 };
 
@@ -24,21 +24,26 @@ impl Modify for AddSecurity {
 
         if let Some(schema) = openapi.components.as_mut() {
             schema.add_security_scheme(
-                "auth_header",
-                SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new(
-                    "authorization",
-                ))),
-            );
-            schema.add_security_scheme(
-                "auth_cookie",
-                SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::new(
-                    "authorization",
-                ))),
+                "bearer",
+                SecurityScheme::Http(
+                    HttpBuilder::new()
+                        .scheme(HttpAuthScheme::Bearer)
+                        .description(Some(
+                            "an api token from the config or the admin \
+                             api, the `pass` field in the body still works \
+                             as a fallback",
+                        ))
+                        .build(),
+                ),
             );
         }
 
-        openapi.security =
-            Some(vec![SecurityRequirement::new("auth_header", [""; 0])]);
+        // an empty requirement marks the bearer token as optional,
+        // since the legacy body `pass` is accepted too
+        openapi.security = Some(vec![
+            SecurityRequirement::new("bearer", [""; 0]),
+            SecurityRequirement::default(),
+        ]);
     }
 }
 
@@ -88,10 +93,10 @@ impl Modify for UpdatePaths {
     fn modify(&self, openapi: &mut oa::OpenApi) {
         if let Some(comps) = &mut openapi.components {
             for (k, v) in comps.schemas.iter_mut() {
-                if let RefOr::T(oa::Schema::Object(obj)) = v {
-                    if obj.title.is_none() {
-                        obj.title = Some(k.clone());
-                    }
+                if let RefOr::T(oa::Schema::Object(obj)) = v
+                    && obj.title.is_none()
+                {
+                    obj.title = Some(k.clone());
                 }
             }
         }
@@ -122,6 +127,7 @@ pub async fn openapi_json() -> impl Responder {
     let mut doc = ApiDoc::openapi();
 
     doc.merge(api::abzar::ApiDoc::openapi());
+    doc.merge(api::admin::ApiDoc::openapi());
     // doc.merge(api::menu::ApiDoc::openapi());
 
     doc_add_prefix(&mut doc, "/api", false);
@@ -0,0 +1,114 @@
+//! `/healthz` answers as long as iris is serving, `/readyz` also checks
+//! what sending a message needs: the database, the bot token and the way
+//! to telegram through the proxies
+
+use crate::config::Config;
+use crate::delivery::telegram;
+use crate::models::AppState;
+use actix_web::{HttpResponse, get, web::Data};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// how long a `getMe` answer is reused, monitors poll often
+const BOT_OK_FOR: Duration = Duration::from_secs(30);
+const BOT_FAILED_FOR: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub ok: bool,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(result: Result<String, String>) -> Self {
+        match result {
+            Ok(detail) => Self { ok: true, detail },
+            Err(detail) => Self { ok: false, detail },
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Ready {
+    /// `ready` or `not_ready`
+    pub status: &'static str,
+    pub checks: BTreeMap<&'static str, Check>,
+    /// each proxy and whether it is being skipped after a failure
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub proxies: BTreeMap<String, Check>,
+}
+
+/// the bot behind the token, from cache when the last answer is fresh
+async fn bot(conf: &Config) -> Result<String, String> {
+    type Cache = HashMap<String, (Instant, Result<String, String>)>;
+    static CACHE: Mutex<Option<Cache>> = Mutex::new(None);
+    let cache = || CACHE.lock().unwrap_or_else(|e| e.into_inner());
+
+    // one entry per bot api server, a test config may point somewhere else
+    let key = crate::breaker::key("telegram", conf.tel_base.expose());
+    if let Some((at, result)) = cache().get_or_insert_default().get(&key) {
+        let fresh = if result.is_ok() { BOT_OK_FOR } else { BOT_FAILED_FOR };
+        if at.elapsed() < fresh {
+            return result.clone();
+        }
+    }
+
+    let result = match telegram::get_me(conf).await {
+        Ok(v) => Ok(format!("@{}", v.username.unwrap_or_default())),
+        Err(e) => Err(e.reason()),
+    };
+    cache()
+        .get_or_insert_default()
+        .insert(key, (Instant::now(), result.clone()));
+    result
+}
+
+async fn database(state: &AppState) -> Result<String, String> {
+    match sqlx::query("SELECT 1").execute(&state.sql).await {
+        Ok(_) => Ok("reachable".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+pub async fn ready(conf: &Config, state: &AppState) -> Ready {
+    let mut checks = BTreeMap::new();
+    checks.insert(
+        "config",
+        Check::new(Ok(format!("{} channels", conf.channels.len()))),
+    );
+    checks.insert("database", Check::new(database(state).await));
+    checks.insert("telegram", Check::new(bot(conf).await));
+
+    let proxies = conf
+        .proxies
+        .iter()
+        .map(|p| {
+            let result = match crate::proxy::is_down(&p.name) {
+                false => Ok("up".to_string()),
+                true => Err("down, skipped for now".to_string()),
+            };
+            (p.name.clone(), Check::new(result))
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let ok = checks.values().all(|v| v.ok);
+    Ready { status: if ok { "ready" } else { "not_ready" }, checks, proxies }
+}
+
+#[get("/healthz")]
+/// Liveness
+async fn r_healthz() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({ "status": "ok" }))
+}
+
+#[get("/readyz")]
+/// Readiness
+async fn r_readyz(state: Data<AppState>) -> HttpResponse {
+    let ready = ready(&Config::get(), &state).await;
+    match ready.status {
+        "ready" => HttpResponse::Ok().json(ready),
+        _ => HttpResponse::ServiceUnavailable().json(ready),
+    }
+}
@@ -0,0 +1,146 @@
+//! token bucket rate limits and daily quotas for channels and api tokens,
+//! the counters live here and not in the config so they outlast it
+
+use crate::models::AppErr;
+use crate::utils::sys_now;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize, utoipa::ToSchema)]
+pub struct Limit {
+    /// requests refilled per second
+    pub rate: Option<f64>,
+    /// requests that can be made at once, defaults to one second of `rate`
+    pub burst: Option<f64>,
+    /// messages per utc day
+    pub daily_messages: Option<u64>,
+    /// text and file bytes per utc day
+    pub daily_bytes: Option<u64>,
+}
+
+struct Bucket {
+    tokens: f64,
+    at: Instant,
+}
+
+#[derive(Default)]
+struct Quota {
+    day: u64,
+    messages: u64,
+    bytes: u64,
+}
+
+#[derive(Default)]
+struct State {
+    buckets: HashMap<String, Bucket>,
+    quotas: HashMap<String, Quota>,
+}
+
+static STATE: Mutex<Option<State>> = Mutex::new(None);
+
+fn state<T>(f: impl FnOnce(&mut State) -> T) -> T {
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    f(state.get_or_insert_default())
+}
+
+fn quota<'a>(state: &'a mut State, key: &str, day: u64) -> &'a mut Quota {
+    let q = state.quotas.entry(key.to_string()).or_default();
+    if q.day != day {
+        *q = Quota { day, ..Default::default() };
+    }
+    q
+}
+
+/// take one request from the bucket and the daily quota of every key, of
+/// all of them or none so a refused request costs nothing. `bytes` is the
+/// size of the request when it is known up front, it has to fit in what is
+/// left of the daily bytes
+pub fn admit(
+    keys: &[(&str, &Limit)], bytes: Option<u64>,
+) -> Result<(), AppErr> {
+    let rs = take(keys, bytes);
+    if let Err(e) = &rs {
+        crate::metrics::rate_limited("iris", e.wait());
+    }
+    rs
+}
+
+fn take(keys: &[(&str, &Limit)], bytes: Option<u64>) -> Result<(), AppErr> {
+    let now = sys_now() as u64;
+    let day = now / 86400;
+    let tomorrow = (day + 1) * 86400 - now;
+
+    state(|state| {
+        for (key, limit) in keys {
+            check(state, key, limit, day, bytes)
+                .map_err(|e| e.retry_after(tomorrow))?;
+            if let Some(wait) = bucket(state, key, limit) {
+                log::warn!("rate limited {key} for {wait}s");
+                return Err(crate::err!(
+                    r,
+                    RateLimited,
+                    format!("{key} is sending too fast")
+                )
+                .retry_after(wait));
+            }
+        }
+
+        for (key, limit) in keys {
+            if limit.rate.is_some_and(|v| v > 0.0)
+                && let Some(b) = state.buckets.get_mut(*key)
+            {
+                b.tokens -= 1.0;
+            }
+            quota(state, key, day).messages += 1;
+        }
+        Ok(())
+    })
+}
+
+/// is there anything left of the daily quotas
+fn check(
+    state: &mut State, key: &str, limit: &Limit, day: u64, bytes: Option<u64>,
+) -> Result<(), AppErr> {
+    let q = quota(state, key, day);
+    if limit.daily_messages.is_some_and(|max| q.messages >= max) {
+        return crate::err!(
+            RateLimited,
+            format!("{key} daily messages are used up")
+        );
+    }
+    let used = q.bytes.saturating_add(bytes.unwrap_or_default());
+    if limit.daily_bytes.is_some_and(|max| q.bytes >= max || used > max) {
+        return crate::err!(
+            RateLimited,
+            format!("{key} daily bytes are used up")
+        );
+    }
+    Ok(())
+}
+
+/// refill the bucket of `key`, the seconds to wait when it is empty
+fn bucket(state: &mut State, key: &str, limit: &Limit) -> Option<u64> {
+    let rate = limit.rate.filter(|v| *v > 0.0)?;
+    let burst = limit.burst.unwrap_or(rate).max(1.0);
+    let b = state
+        .buckets
+        .entry(key.to_string())
+        .or_insert(Bucket { tokens: burst, at: Instant::now() });
+    let elapsed = b.at.elapsed().as_secs_f64();
+    b.tokens = (b.tokens + elapsed * rate).min(burst);
+    b.at = Instant::now();
+
+    if b.tokens < 1.0 {
+        let wait = ((1.0 - b.tokens) / rate).ceil() as u64;
+        return Some(wait.max(1));
+    }
+    None
+}
+
+/// count the bytes of an admitted message against the daily quota,
+/// they are only known once the upload is done
+pub fn used_bytes(key: &str, bytes: u64) {
+    let day = sys_now() as u64 / 86400;
+    state(|state| quota(state, key, day).bytes += bytes);
+}
@@ -1,59 +1,223 @@
-use std::io::Write;
+//! the log goes to stderr as text, colored on a terminal, or as one json
+//! object per line. which targets log at which level is set with
+//! directives like `info,iris::proxy=debug,sqlx=warn` from `IRIS_LOG` or
+//! `[log] filter` in the config
 
-struct MasterLogger;
-impl log::Log for MasterLogger {
-    fn enabled(&self, md: &log::Metadata) -> bool {
-        let t = md.target();
-        if t.starts_with("hyper_util") || t.starts_with("tracing") {
-            return false;
+use log::LevelFilter;
+use std::io::{IsTerminal, Write};
+use std::sync::RwLock;
+
+/// targets that are too loud to log unless a directive names them
+const QUIET: &str = "hyper_util=off,tracing=off,sqlx=off,h2::codec=off";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Log {
+    /// directives, `IRIS_LOG` wins over this one
+    pub filter: Option<String>,
+    #[serde(default)]
+    pub format: Format,
+}
+
+impl Log {
+    pub fn check(&self) -> Result<(), String> {
+        if let Some(filter) = &self.filter {
+            Filter::parse(filter).map_err(|e| format!("log.filter: {e}"))?;
         }
-        if t.starts_with("sqlx") || t.starts_with("h2::codec") {
-            return false;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    level: LevelFilter,
+    /// by target, the longest first so the closest one is found first
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    /// comma separated `level` or `target=level`, a later one wins
+    pub fn parse(directives: &str) -> Result<Self, String> {
+        let mut filter = Self { level: LevelFilter::Debug, targets: vec![] };
+        for d in QUIET.split(',').chain(directives.split(',')) {
+            let d = d.trim();
+            if d.is_empty() {
+                continue;
+            }
+            let level = |v: &str| {
+                v.trim().parse::<LevelFilter>().map_err(|_| {
+                    format!("bad level {v:?} in {d:?}, use off to trace")
+                })
+            };
+            match d.split_once('=') {
+                None => filter.level = level(d)?,
+                Some((target, _)) if target.trim().is_empty() => {
+                    return Err(format!("no target in {d:?}"));
+                }
+                Some((target, v)) => {
+                    let (target, v) = (target.trim().to_string(), level(v)?);
+                    filter.targets.retain(|(t, _)| *t != target);
+                    filter.targets.push((target, v));
+                }
+            }
         }
-        // if md.level() > log::Level::Info {
-        //     return false;
-        // }
+        filter.targets.sort_by_key(|(t, _)| std::cmp::Reverse(t.len()));
+        Ok(filter)
+    }
 
-        true
+    /// the level of the target itself or of the closest module above it
+    pub fn level(&self, target: &str) -> LevelFilter {
+        self.targets
+            .iter()
+            .find(|(t, _)| {
+                target.strip_prefix(t.as_str()).is_some_and(|rest| {
+                    rest.is_empty() || rest.starts_with("::")
+                })
+            })
+            .map_or(self.level, |(_, v)| *v)
+    }
+
+    fn max(&self) -> LevelFilter {
+        self.targets.iter().map(|(_, v)| *v).fold(self.level, Ord::max)
+    }
+}
+
+struct Settings {
+    filter: Filter,
+    format: Format,
+    color: bool,
+}
+
+static STATE: RwLock<Option<Settings>> = RwLock::new(None);
+
+struct MasterLogger;
+impl log::Log for MasterLogger {
+    fn enabled(&self, md: &log::Metadata) -> bool {
+        let state = STATE.read().unwrap_or_else(|e| e.into_inner());
+        state
+            .as_ref()
+            .is_some_and(|s| md.level() <= s.filter.level(md.target()))
     }
 
     fn log(&self, record: &log::Record) {
-        if !self.enabled(record.metadata()) {
+        let state = STATE.read().unwrap_or_else(|e| e.into_inner());
+        let Some(s) = state.as_ref() else { return };
+        if record.level() > s.filter.level(record.target()) {
             return;
         }
 
-        let level = match record.level() {
-            log::Level::Trace => ["\x1b[36m", "T", "Trace"],
-            log::Level::Debug => ["\x1b[35m", "D", "Debug"],
-            log::Level::Info => ["\x1b[32m", "I", "Info"],
-            log::Level::Warn => ["\x1b[33m", "W", "Warn"],
-            log::Level::Error => ["\x1b[31m", "E", "Error"],
+        let line = match s.format {
+            Format::Json => json(record, &now()),
+            Format::Text => text(record, &now(), s.color),
         };
-        let _ = writeln!(
-            std::io::stderr(),
-            "{{{}{}\x1b[0m}}[{}{}\x1b[0m]{{{}{}\x1b[32m:\x1b[93m{}\x1b[0m}}: {}",
-            level[0],
-            now(),
-            level[0],
-            level[1],
-            level[0],
-            record.target(),
-            record.line().unwrap_or_default(),
-            record.args(),
-        );
+        let _ = writeln!(std::io::stderr(), "{line}");
     }
 
     fn flush(&self) {}
 }
 
+/// the filter from `IRIS_LOG`, or from `config` when it is not set
+fn filter(config: Option<&str>) -> Filter {
+    let env = std::env::var("IRIS_LOG").ok();
+    let Some(directives) = env.as_deref().or(config) else {
+        return Filter::parse("").expect("the quiet targets parse");
+    };
+    Filter::parse(directives).unwrap_or_else(|e| {
+        let _ = writeln!(std::io::stderr(), "[log] ignoring the filter: {e}");
+        Filter::parse("").expect("the quiet targets parse")
+    })
+}
+
+fn apply(filter: Filter, format: Format) {
+    let color = format == Format::Text
+        && std::io::stderr().is_terminal()
+        && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty());
+    log::set_max_level(filter.max());
+    *STATE.write().unwrap_or_else(|e| e.into_inner()) =
+        Some(Settings { filter, format, color });
+}
+
+/// log as text with `IRIS_LOG` until the config is known
 pub fn setup() {
     log::set_logger(&MasterLogger).expect("could not init logger");
-    log::set_max_level(log::LevelFilter::Debug);
+    apply(filter(None), Format::Text);
 }
 
-fn now() -> u64 {
-    std::time::SystemTime::now()
+/// use the `[log]` section of the config, at startup and on every reload
+pub fn configure(log: &Log) {
+    apply(filter(log.filter.as_deref()), log.format);
+}
+
+pub fn text(record: &log::Record, ts: &str, color: bool) -> String {
+    let (c, reset, green, yellow) = match color {
+        false => ("", "", "", ""),
+        true => (
+            match record.level() {
+                log::Level::Trace => "\x1b[36m",
+                log::Level::Debug => "\x1b[35m",
+                log::Level::Info => "\x1b[32m",
+                log::Level::Warn => "\x1b[33m",
+                log::Level::Error => "\x1b[31m",
+            },
+            "\x1b[0m",
+            "\x1b[32m",
+            "\x1b[93m",
+        ),
+    };
+    let level = &record.level().as_str()[..1];
+    format!(
+        "{{{c}{ts}{reset}}}[{c}{level}{reset}]\
+         {{{c}{}{green}:{yellow}{}{reset}}}: {}",
+        record.target(),
+        record.line().unwrap_or_default(),
+        record.args(),
+    )
+}
+
+pub fn json(record: &log::Record, ts: &str) -> String {
+    serde_json::json!({
+        "ts": ts,
+        "level": record.level().as_str().to_lowercase(),
+        "target": record.target(),
+        "line": record.line(),
+        "msg": record.args().to_string(),
+    })
+    .to_string()
+}
+
+fn now() -> String {
+    let now = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_secs()
+        .unwrap_or_default();
+    rfc3339(now.as_secs() as i64, now.subsec_millis())
+}
+
+/// `2006-01-02T15:04:05.000Z` for seconds since the epoch, in utc
+pub fn rfc3339(secs: i64, millis: u32) -> String {
+    let (days, time) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    // days to a civil date, from howardhinnant.github.io/date_algorithms
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{millis:03}Z",
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
 }
@@ -1,17 +1,36 @@
 use crate::config::Config;
+use crate::models::AppState;
 use actix_files as af;
 use actix_multipart::form::MultipartFormConfig;
 use actix_web::{
-    App, HttpServer, middleware,
-    web::{ServiceConfig, scope},
+    App, HttpServer,
+    http::KeepAlive,
+    middleware,
+    web::{Data, ServiceConfig, scope},
 };
 pub use models::{AppErr, ErrorCode};
+use std::time::Duration;
 
 mod api;
+mod audit;
+mod auth;
+mod breaker;
+mod cli;
 mod config;
+mod db;
+mod delivery;
 mod docs;
+mod health;
+mod limits;
 mod logger;
+mod metrics;
 mod models;
+mod proxy;
+mod redact;
+mod secret;
+mod server;
+#[cfg(test)]
+mod tests;
 mod utils;
 
 fn config_app(app: &mut ServiceConfig) {
@@ -21,35 +40,51 @@ fn config_app(app: &mut ServiceConfig) {
         // app.service(af::Files::new("/record", Config::RECORD_DIR));
     }
 
+    // files are streamed in send-file, forms are only text
+    app.app_data(
+        MultipartFormConfig::default()
+            .total_limit(1024 * 1024)
+            .memory_limit(1024 * 1024)
+            .error_handler(|e, _rq| {
+                log::error!("mpf: {e:#?}");
+                err!(r, FileTooBig, e.to_string()).into()
+            }),
+    );
+
     app.service(docs::openapi_json).service(docs::rapidoc);
-    app.service(scope("/api").service(api::abzar::router()));
+    app.service(metrics::r_metrics);
+    app.service(health::r_healthz).service(health::r_readyz);
+    app.service(
+        scope("/api")
+            .service(api::abzar::router())
+            .service(api::admin::router()),
+    );
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     logger::setup();
-    Config::get();
+    match cli::Command::parse() {
+        Ok(Some(cmd)) => std::process::exit(cli::run(cmd).await),
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(2);
+        }
+    }
 
-    // let cpt = SqliteConnectOptions::from_str("sqlite://main.db")
-    //     .expect("could not init sqlite connection options")
-    //     .journal_mode(SqliteJournalMode::Off);
-    //
-    // let pool = SqlitePool::connect_with(cpt).await.expect("sqlite connection");
+    let conf = Config::get();
+    logger::configure(&conf.log);
+    Config::watch();
+    proxy::watch();
 
-    // let app_state = Data::new(AppState::new().expect("app state"));
+    let pool = db::connect(&conf.db).await.expect("sqlite connection");
+    let app_state = Data::new(AppState { sql: pool });
 
-    let server = HttpServer::new(move || {
+    let mut server = HttpServer::new(move || {
         App::new()
+            .wrap(middleware::from_fn(metrics::record))
             .wrap(middleware::Logger::new("%s %r %Ts"))
-            .app_data(
-                MultipartFormConfig::default()
-                    .total_limit(200 * 1024 * 1024)
-                    .memory_limit(200 * 1024 * 1024)
-                    .error_handler(|e, _rq| {
-                        log::error!("mpf: {e:#?}");
-                        err!(r, FileTooBig, e.to_string()).into()
-                    }),
-            )
             // .wrap(
             //     actix_cors::Cors::default()
             //         .allowed_origin("https://sky.gooje.app")
@@ -57,21 +92,26 @@ async fn main() -> std::io::Result<()> {
             //         .allowed_origin("http://localhost:8008")
             //         .allowed_methods(["GET", "POST"]),
             // )
-            // .app_data(app_state.clone())
+            .app_data(app_state.clone())
             .configure(config_app)
-    });
-
-    // server.bind(("127.0.0.1", 7023)).unwrap().run().await
+    })
+    .on_connect(server::on_connect);
 
-    let server = if cfg!(debug_assertions) {
-        server.bind(("127.0.0.1", 7023)).unwrap()
-    } else {
-        use std::os::unix::fs::PermissionsExt;
-        const PATH: &str = "/usr/share/nginx/socks/iris.sock";
-        let server = server.bind_uds(PATH).unwrap();
-        std::fs::set_permissions(PATH, std::fs::Permissions::from_mode(0o777))?;
-        server
-    };
+    if let Some(workers) = conf.server.workers {
+        server = server.workers(workers);
+    }
+    if let Some(secs) = conf.server.keep_alive {
+        server = server.keep_alive(match secs {
+            0 => KeepAlive::Disabled,
+            v => KeepAlive::Timeout(Duration::from_secs(v)),
+        });
+    }
+    for bound in conf.server.bind()? {
+        server = match bound {
+            server::Bound::Tcp(v) => server.listen(v)?,
+            server::Bound::Unix(v) => server.listen_uds(v)?,
+        };
+    }
 
     server.run().await
 }
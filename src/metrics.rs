@@ -0,0 +1,242 @@
+//! prometheus metrics, kept in memory and written out in the text format
+//! by `GET /metrics`. it needs no auth, keep it off the public proxy
+
+use crate::audit::Audit;
+use crate::config::Config;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::ContentType;
+use actix_web::middleware::Next;
+use actix_web::{HttpMessage, HttpResponse, get};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// upper bounds of the histogram buckets, in seconds
+const BUCKETS: [f64; 11] =
+    [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 10.0, 60.0];
+
+/// every metric with its type and help, in the order they are written
+const METRICS: &[(&str, &str, &str)] = &[
+    (
+        "iris_requests_total",
+        "counter",
+        "http requests by endpoint, channel and status",
+    ),
+    (
+        "iris_request_duration_seconds",
+        "histogram",
+        "time to answer http requests by endpoint",
+    ),
+    (
+        "iris_requests_in_flight",
+        "gauge",
+        "http requests being answered right now",
+    ),
+    (
+        "iris_deliveries_in_flight",
+        "gauge",
+        "messages being handed to a backend right now",
+    ),
+    (
+        "iris_telegram_duration_seconds",
+        "histogram",
+        "bot api call latency by method",
+    ),
+    (
+        "iris_telegram_errors_total",
+        "counter",
+        "failed bot api calls by method and http status, 0 for no answer",
+    ),
+    (
+        "iris_upload_bytes_total",
+        "counter",
+        "bytes of uploaded files by channel",
+    ),
+    (
+        "iris_rate_limited_total",
+        "counter",
+        "requests refused by a rate limit, by iris or telegram",
+    ),
+    (
+        "iris_rate_limit_wait_seconds_total",
+        "counter",
+        "seconds clients were told to wait by a rate limit",
+    ),
+    (
+        "iris_breaker_open",
+        "gauge",
+        "1 while the circuit breaker of a backend is open",
+    ),
+];
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+#[derive(Default)]
+struct State {
+    /// counters and gauges by name, then by their rendered labels
+    values: BTreeMap<&'static str, BTreeMap<String, f64>>,
+    histograms: BTreeMap<&'static str, BTreeMap<String, Histogram>>,
+}
+
+static STATE: Mutex<Option<State>> = Mutex::new(None);
+
+fn state<T>(f: impl FnOnce(&mut State) -> T) -> T {
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    f(state.get_or_insert_default())
+}
+
+fn labels(labels: &[(&str, &str)]) -> String {
+    let mut out = String::new();
+    for (i, (k, v)) in labels.iter().enumerate() {
+        let v =
+            v.replace('\\', r"\\").replace('"', r#"\""#).replace('\n', r"\n");
+        let sep = if i == 0 { "" } else { "," };
+        let _ = write!(out, "{sep}{k}=\"{v}\"");
+    }
+    out
+}
+
+pub fn add(name: &'static str, l: &[(&str, &str)], by: f64) {
+    let l = labels(l);
+    state(|s| *s.values.entry(name).or_default().entry(l).or_default() += by)
+}
+
+pub fn set(name: &'static str, l: &[(&str, &str)], value: f64) {
+    let l = labels(l);
+    state(|s| s.values.entry(name).or_default().insert(l, value));
+}
+
+pub fn observe(name: &'static str, l: &[(&str, &str)], took: Duration) {
+    let l = labels(l);
+    let secs = took.as_secs_f64();
+    state(|s| {
+        let h = s.histograms.entry(name).or_default().entry(l).or_default();
+        for (i, le) in BUCKETS.iter().enumerate() {
+            if secs <= *le {
+                h.buckets[i] += 1;
+            }
+        }
+        h.sum += secs;
+        h.count += 1;
+    })
+}
+
+/// a rate limit refused a request and told it to wait `wait` seconds
+pub fn rate_limited(by: &str, wait: Option<u64>) {
+    add("iris_rate_limited_total", &[("by", by)], 1.0);
+    let wait = wait.unwrap_or_default() as f64;
+    add("iris_rate_limit_wait_seconds_total", &[("by", by)], wait);
+}
+
+/// keeps a gauge one higher while it lives
+pub struct InFlight(&'static str, String);
+
+impl InFlight {
+    pub fn new(name: &'static str, l: &[(&str, &str)]) -> Self {
+        let l = labels(l);
+        state(|s| {
+            *s.values.entry(name).or_default().entry(l.clone()).or_default() +=
+                1.0
+        });
+        Self(name, l)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        state(|s| {
+            if let Some(v) =
+                s.values.get_mut(self.0).and_then(|v| v.get_mut(&self.1))
+            {
+                *v -= 1.0;
+            }
+        })
+    }
+}
+
+/// every metric in the prometheus text format
+pub fn render() -> String {
+    let mut out = String::new();
+    state(|s| {
+        for (name, kind, help) in METRICS {
+            let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} {kind}");
+            for (l, v) in s.values.get(name).into_iter().flatten() {
+                let l =
+                    if l.is_empty() { l.clone() } else { format!("{{{l}}}") };
+                let _ = writeln!(out, "{name}{l} {v}");
+            }
+            for (l, h) in s.histograms.get(name).into_iter().flatten() {
+                let sep = if l.is_empty() { "" } else { "," };
+                for (le, n) in BUCKETS.iter().zip(h.buckets) {
+                    let _ = writeln!(
+                        out,
+                        "{name}_bucket{{{l}{sep}le=\"{le}\"}} {n}"
+                    );
+                }
+                let _ = writeln!(
+                    out,
+                    "{name}_bucket{{{l}{sep}le=\"+Inf\"}} {}",
+                    h.count
+                );
+                let l =
+                    if l.is_empty() { l.clone() } else { format!("{{{l}}}") };
+                let _ = writeln!(out, "{name}_sum{l} {}", h.sum);
+                let _ = writeln!(out, "{name}_count{l} {}", h.count);
+            }
+        }
+    });
+    out
+}
+
+/// count every request with its route, not its path, so ids and unknown
+/// urls do not make a new series each
+pub async fn record(
+    rq: ServiceRequest, next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let started = Instant::now();
+    let _in_flight = InFlight::new("iris_requests_in_flight", &[]);
+    let endpoint = rq.match_pattern().unwrap_or_else(|| "unmatched".into());
+
+    let rs = next.call(rq).await;
+
+    let status = match &rs {
+        Ok(rs) => rs.status(),
+        Err(e) => e.as_response_error().status_code(),
+    };
+    // the channel as the audit log saw it, if it is a real one
+    let channel = rs.as_ref().ok().and_then(|rs| {
+        let channel = rs.request().extensions().get::<Audit>()?.channel()?;
+        Config::get().channels.contains_key(&channel).then_some(channel)
+    });
+
+    add(
+        "iris_requests_total",
+        &[
+            ("endpoint", &endpoint),
+            ("channel", channel.as_deref().unwrap_or("")),
+            ("status", status.as_str()),
+        ],
+        1.0,
+    );
+    let took = started.elapsed();
+    observe("iris_request_duration_seconds", &[("endpoint", &endpoint)], took);
+
+    rs
+}
+
+#[get("/metrics")]
+/// Prometheus Metrics
+async fn r_metrics() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(ContentType(
+            "text/plain; version=0.0.4; charset=utf-8".parse().expect("mime"),
+        ))
+        .body(render())
+}
@@ -0,0 +1,121 @@
+use crate::models::{AppErr, SortOrder};
+use sqlx::{QueryBuilder, Sqlite, SqlitePool};
+
+#[derive(
+    Debug,
+    Default,
+    Clone,
+    sqlx::FromRow,
+    serde::Serialize,
+    utoipa::ToSchema
+)]
+/// one abzar request and how it went
+pub struct AuditEntry {
+    pub id: i64,
+    pub at: i64,
+    pub endpoint: String,
+    /// not known when the request was refused before its body was read
+    pub channel: Option<String>,
+    /// `token:<name>`, `api:<name>`, `peer:<name>`, `hmac:<channel>` or
+    /// `pass`
+    pub identity: Option<String>,
+    pub ip: Option<String>,
+    /// sha256 of the text in hex
+    pub text_hash: Option<String>,
+    /// the first few characters of the text
+    pub text_preview: Option<String>,
+    pub file_name: Option<String>,
+    pub file_mime: Option<String>,
+    pub file_size: Option<i64>,
+    pub message_id: Option<i64>,
+    /// http status of the response
+    pub status: u16,
+    /// error code of a failed request
+    pub error: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize, utoipa::IntoParams)]
+pub struct AuditFilter {
+    pub channel: Option<String>,
+    pub identity: Option<String>,
+    pub ip: Option<String>,
+    pub endpoint: Option<String>,
+    /// only failed requests when true, only successful ones when false
+    pub failed: Option<bool>,
+    /// unix time, inclusive
+    pub since: Option<i64>,
+    /// unix time, exclusive
+    pub until: Option<i64>,
+    #[serde(default)]
+    #[param(inline)]
+    pub order: SortOrder,
+    #[param(example = 0)]
+    #[serde(default)]
+    pub page: u32,
+}
+
+impl AuditEntry {
+    pub const PAGE_SIZE: u32 = 100;
+
+    pub async fn insert(&self, sql: &SqlitePool) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO audit (at, endpoint, channel, identity, ip, \
+             text_hash, text_preview, file_name, file_mime, file_size, \
+             message_id, status, error) \
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(self.at)
+        .bind(&self.endpoint)
+        .bind(&self.channel)
+        .bind(&self.identity)
+        .bind(&self.ip)
+        .bind(&self.text_hash)
+        .bind(&self.text_preview)
+        .bind(&self.file_name)
+        .bind(&self.file_mime)
+        .bind(self.file_size)
+        .bind(self.message_id)
+        .bind(self.status)
+        .bind(&self.error)
+        .execute(sql)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn list(
+        sql: &SqlitePool, filter: &AuditFilter,
+    ) -> Result<Vec<Self>, AppErr> {
+        let mut qb = QueryBuilder::<Sqlite>::new("SELECT * FROM audit WHERE 1");
+
+        let text = [
+            ("channel", &filter.channel),
+            ("identity", &filter.identity),
+            ("ip", &filter.ip),
+            ("endpoint", &filter.endpoint),
+        ];
+        for (column, value) in text {
+            if let Some(v) = value {
+                qb.push(format!(" AND {column} = ")).push_bind(v.clone());
+            }
+        }
+        match filter.failed {
+            Some(true) => qb.push(" AND error IS NOT NULL"),
+            Some(false) => qb.push(" AND error IS NULL"),
+            None => &mut qb,
+        };
+        if let Some(since) = filter.since {
+            qb.push(" AND at >= ").push_bind(since);
+        }
+        if let Some(until) = filter.until {
+            qb.push(" AND at < ").push_bind(until);
+        }
+
+        qb.push(format!(" ORDER BY id {} LIMIT ", filter.order))
+            .push_bind(Self::PAGE_SIZE)
+            .push(" OFFSET ")
+            .push_bind(i64::from(filter.page) * i64::from(Self::PAGE_SIZE));
+
+        Ok(qb.build_query_as().fetch_all(sql).await?)
+    }
+}
@@ -3,6 +3,10 @@ use actix_web::{HttpResponse, web::Json};
 pub type Horp = Result<HttpResponse, super::AppErr>;
 pub type Jorp<T> = Result<Json<T>, super::AppErr>;
 
+pub struct AppState {
+    pub sql: sqlx::SqlitePool,
+}
+
 // #[derive(serde::Deserialize, utoipa::IntoParams)]
 // pub struct ListParams {
 //     #[param(example = 0)]
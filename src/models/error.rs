@@ -1,4 +1,5 @@
-use actix_web::{HttpResponse, ResponseError, body::BoxBody, http::StatusCode};
+use actix_web::http::{StatusCode, header::RETRY_AFTER};
+use actix_web::{HttpResponse, ResponseError, body::BoxBody};
 use serde::Serialize;
 use tokio::task::JoinError;
 use utoipa::ToSchema;
@@ -8,7 +9,7 @@ use utoipa::ToSchema;
 pub enum ErrorCode {
     #[default]
     Unknown = 0,
-    // BadRequest,
+    BadRequest,
     Forbidden,
     ForbiddenSelfEdit,
     BadAuth,
@@ -21,13 +22,20 @@ pub enum ErrorCode {
 
     SendFailed,
     FileTooBig,
+    Unsupported,
+    SecretDetected,
+    /// the backend is down, see [`crate::breaker`]
+    CircuitOpen,
 }
 
 impl ErrorCode {
     fn status(&self) -> u16 {
         match self {
+            Self::BadRequest => 400,
             Self::NotUnique => 400,
             Self::FileTooBig => 400,
+            Self::Unsupported => 400,
+            Self::SecretDetected => 400,
 
             Self::IndexOutOfBounds => 400,
 
@@ -37,6 +45,8 @@ impl ErrorCode {
 
             Self::RateLimited => 429,
 
+            Self::CircuitOpen => 503,
+
             Self::SendFailed => 500,
             Self::Unknown => 500,
             Self::ServerError | Self::DatabaseError => 500,
@@ -50,6 +60,9 @@ pub struct AppErr {
     status: u16,
     code: ErrorCode,
     debug: Option<String>,
+    /// seconds until trying again makes sense, sent as `Retry-After`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_after: Option<u64>,
 }
 
 impl AppErr {
@@ -58,7 +71,12 @@ impl AppErr {
     // }
 
     pub fn server_error() -> Self {
-        Self { status: 500, code: ErrorCode::ServerError, debug: None }
+        Self {
+            status: 500,
+            code: ErrorCode::ServerError,
+            debug: None,
+            retry_after: None,
+        }
     }
 
     pub fn debug(mut self, debug: &str) -> Self {
@@ -66,6 +84,28 @@ impl AppErr {
         self
     }
 
+    /// the debug text, or the code when there is none
+    pub fn reason(&self) -> String {
+        match &self.debug {
+            Some(v) => v.clone(),
+            None => format!("{:?}", self.code),
+        }
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        self.code
+    }
+
+    /// the `Retry-After` seconds, if any
+    pub fn wait(&self) -> Option<u64> {
+        self.retry_after
+    }
+
+    pub fn retry_after(mut self, secs: u64) -> Self {
+        self.retry_after = Some(secs);
+        self
+    }
+
     // pub fn code<C: Into<u16>>(mut self, code: C) -> Self {
     //     self.code = code.into();
     //     self
@@ -80,25 +120,30 @@ impl std::fmt::Display for AppErr {
     }
 }
 
-// impl From<sqlx::Error> for AppErr {
-//     fn from(value: sqlx::Error) -> Self {
-//         log::error!("sqlx error: {value:?}");
-//         match value {
-//             sqlx::Error::RowNotFound => ErrorCode::NotFound,
-//             sqlx::Error::Database(e) => match e.code() {
-//                 Some(c) if c == "2067" => ErrorCode::NotUnique,
-//                 Some(c) if c == "787" => ErrorCode::NotFound,
-//                 _ => ErrorCode::DatabaseError,
-//             },
-//             _ => ErrorCode::DatabaseError,
-//         }
-//         .into()
-//     }
-// }
+impl From<sqlx::Error> for AppErr {
+    fn from(value: sqlx::Error) -> Self {
+        log::error!("sqlx error: {value:?}");
+        match value {
+            sqlx::Error::RowNotFound => ErrorCode::NotFound,
+            sqlx::Error::Database(e) => match e.code() {
+                Some(c) if c == "2067" => ErrorCode::NotUnique,
+                Some(c) if c == "787" => ErrorCode::NotFound,
+                _ => ErrorCode::DatabaseError,
+            },
+            _ => ErrorCode::DatabaseError,
+        }
+        .into()
+    }
+}
 
 impl From<ErrorCode> for AppErr {
     fn from(value: ErrorCode) -> Self {
-        Self { status: value.status(), debug: None, code: value }
+        Self {
+            status: value.status(),
+            debug: None,
+            code: value,
+            retry_after: None,
+        }
     }
 }
 
@@ -108,6 +153,12 @@ impl From<ErrorCode> for AppErr {
 //     }
 // }
 
+impl From<actix_multipart::MultipartError> for AppErr {
+    fn from(value: actix_multipart::MultipartError) -> Self {
+        Self::from(ErrorCode::BadRequest).debug(&value.to_string())
+    }
+}
+
 impl From<JoinError> for AppErr {
     fn from(value: JoinError) -> Self {
         Self::from(ErrorCode::ServerError)
@@ -122,7 +173,11 @@ impl ResponseError for AppErr {
     }
 
     fn error_response(&self) -> HttpResponse<BoxBody> {
-        HttpResponse::build(self.status_code()).json(self)
+        let mut rs = HttpResponse::build(self.status_code());
+        if let Some(secs) = self.retry_after {
+            rs.insert_header((RETRY_AFTER, secs));
+        }
+        rs.json(self)
     }
 }
 
@@ -1,5 +1,9 @@
+mod audit;
 mod common;
 mod error;
+mod token;
 
+pub use audit::{AuditEntry, AuditFilter};
 pub use common::*;
 pub use error::{AppErr, ErrorCode};
+pub use token::ApiToken;
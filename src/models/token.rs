@@ -0,0 +1,158 @@
+use crate::auth::{Bearer, Op};
+use crate::config::Config;
+use crate::limits::Limit;
+use crate::models::AppErr;
+use crate::utils::{rand_str, sys_now};
+use sha2::{Digest, Sha256};
+use sqlx::{SqlitePool, types::Json};
+use std::collections::HashSet;
+use subtle::ConstantTimeEq;
+
+#[derive(Debug, sqlx::FromRow, serde::Serialize, utoipa::ToSchema)]
+/// an api token made with the admin api, its secret is only shown once
+pub struct ApiToken {
+    pub id: i64,
+    pub name: String,
+    #[serde(skip)]
+    pub hash: String,
+    /// channels the token may use, all of them when not set
+    #[schema(value_type = Option<Vec<String>>)]
+    pub channels: Option<Json<HashSet<String>>>,
+    /// operations the token may do, all but admin when not set
+    #[schema(value_type = Option<Vec<Op>>)]
+    pub ops: Option<Json<HashSet<Op>>>,
+    /// rate limit and daily quotas of the token, none when not set
+    #[sqlx(rename = "limits")]
+    #[schema(value_type = Option<Limit>)]
+    pub limit: Option<Json<Limit>>,
+    pub created_at: i64,
+    pub last_used: Option<i64>,
+    /// the token stops working at this time, never when not set
+    pub expires_at: Option<i64>,
+    pub revoked_at: Option<i64>,
+}
+
+/// a fresh secret and its hash, the secrets are random enough that a
+/// plain sha256 does the job of a slow pass hash
+fn secret() -> (String, String) {
+    let secret = rand_str(Config::TOKEN_ABC, 40);
+    let hash = hex::encode(Sha256::digest(&secret));
+    (secret, hash)
+}
+
+impl ApiToken {
+    pub const PREFIX: &str = "iris_";
+
+    /// the token as clients send it, `iris_<id>_<secret>`
+    fn token(&self, secret: &str) -> String {
+        format!("{}{}_{secret}", Self::PREFIX, self.id)
+    }
+
+    pub async fn create(
+        sql: &SqlitePool, name: &str, channels: Option<HashSet<String>>,
+        ops: Option<HashSet<Op>>, limit: Option<Limit>,
+        expires_at: Option<i64>,
+    ) -> Result<(Self, String), AppErr> {
+        let (secret, hash) = secret();
+        let t: Self = sqlx::query_as(
+            "INSERT INTO tokens (name, hash, channels, ops, limits, \
+             created_at, expires_at) VALUES (?, ?, ?, ?, ?, ?, ?) RETURNING *",
+        )
+        .bind(name)
+        .bind(hash)
+        .bind(channels.map(Json))
+        .bind(ops.map(Json))
+        .bind(limit.map(Json))
+        .bind(sys_now())
+        .bind(expires_at)
+        .fetch_one(sql)
+        .await?;
+
+        let token = t.token(&secret);
+        Ok((t, token))
+    }
+
+    pub async fn list(sql: &SqlitePool) -> Result<Vec<Self>, AppErr> {
+        Ok(sqlx::query_as("SELECT * FROM tokens ORDER BY id")
+            .fetch_all(sql)
+            .await?)
+    }
+
+    /// a revoked token stays listed, revoking it again changes nothing
+    pub async fn revoke(sql: &SqlitePool, id: i64) -> Result<Self, AppErr> {
+        Ok(sqlx::query_as(
+            "UPDATE tokens SET revoked_at = COALESCE(revoked_at, ?) \
+             WHERE id = ? RETURNING *",
+        )
+        .bind(sys_now())
+        .bind(id)
+        .fetch_one(sql)
+        .await?)
+    }
+
+    /// give the token a new secret, the old one stops working right away
+    pub async fn rotate(
+        sql: &SqlitePool, id: i64, expires_at: Option<i64>,
+    ) -> Result<(Self, String), AppErr> {
+        let (secret, hash) = secret();
+        let t: Self = sqlx::query_as(
+            "UPDATE tokens SET hash = ?, expires_at = ? \
+             WHERE id = ? AND revoked_at IS NULL RETURNING *",
+        )
+        .bind(hash)
+        .bind(expires_at)
+        .bind(id)
+        .fetch_one(sql)
+        .await?;
+
+        let token = t.token(&secret);
+        Ok((t, token))
+    }
+
+    /// find the live token matching a bearer token and mark it used
+    pub async fn verify(
+        sql: &SqlitePool, token: &str,
+    ) -> Result<Option<Self>, AppErr> {
+        let parsed = token.strip_prefix(Self::PREFIX).and_then(|v| {
+            let (id, secret) = v.split_once('_')?;
+            Some((id.parse::<i64>().ok()?, secret))
+        });
+        let Some((id, secret)) = parsed else { return Ok(None) };
+
+        let t: Option<Self> =
+            sqlx::query_as("SELECT * FROM tokens WHERE id = ?")
+                .bind(id)
+                .fetch_optional(sql)
+                .await?;
+        let Some(mut t) = t else { return Ok(None) };
+
+        let hash = hex::encode(Sha256::digest(secret));
+        if !bool::from(hash.as_bytes().ct_eq(t.hash.as_bytes())) {
+            return Ok(None);
+        }
+
+        let now = sys_now();
+        if t.revoked_at.is_some() || t.expires_at.is_some_and(|v| v <= now) {
+            return Ok(None);
+        }
+
+        sqlx::query("UPDATE tokens SET last_used = ? WHERE id = ?")
+            .bind(now)
+            .bind(id)
+            .execute(sql)
+            .await?;
+        t.last_used = Some(now);
+
+        Ok(Some(t))
+    }
+
+    pub fn bearer(&self) -> Bearer {
+        Bearer {
+            kind: "api",
+            name: self.name.clone(),
+            channels: self.channels.as_ref().map(|v| v.0.clone()),
+            ops: self.ops.as_ref().map(|v| v.0.clone()),
+            limit: self.limit.as_ref().map(|v| v.0.clone()),
+        }
+    }
+}
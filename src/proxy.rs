@@ -0,0 +1,147 @@
+//! outbound proxies for the bot api, tried in config order with the ones
+//! that failed moved to the back for a while. the health lives here and
+//! not in the config so it outlasts a reload
+
+use crate::config::Config;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// how long a failed proxy is passed over, doubled on every failure in a row
+const BACKOFF: Duration = Duration::from_secs(30);
+const MAX_BACKOFF: Duration = Duration::from_secs(600);
+/// how often every proxy is tried with `getMe`
+const CHECK_EVERY: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub struct Proxy {
+    pub name: String,
+    /// a client that goes through this proxy only
+    pub client: reqwest::Client,
+}
+
+#[derive(Default)]
+struct Health {
+    failures: u32,
+    down_until: Option<Instant>,
+}
+
+static STATE: Mutex<Option<HashMap<String, Health>>> = Mutex::new(None);
+
+fn state<T>(f: impl FnOnce(&mut HashMap<String, Health>) -> T) -> T {
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    f(state.get_or_insert_default())
+}
+
+fn failed(name: &str, e: &reqwest::Error) {
+    // the url holds the bot token, only the cause is logged
+    let e =
+        std::error::Error::source(e).map_or(e.to_string(), |v| v.to_string());
+    state(|s| {
+        let h = s.entry(name.to_string()).or_default();
+        h.failures += 1;
+        let wait = BACKOFF
+            .saturating_mul(1 << (h.failures - 1).min(10))
+            .min(MAX_BACKOFF);
+        h.down_until = Some(Instant::now() + wait);
+        log::warn!("[proxy] {name} failed, skipped for {wait:?}: {e}");
+    })
+}
+
+fn worked(name: &str) {
+    state(|s| {
+        if let Some(h) = s.remove(name) {
+            log::info!("[proxy] {name} is back after {} failures", h.failures);
+        }
+    })
+}
+
+/// is the proxy being passed over right now
+pub fn is_down(name: &str) -> bool {
+    let now = Instant::now();
+    state(|s| s.get(name).and_then(|h| h.down_until).is_some_and(|v| v > now))
+}
+
+/// the proxies to try in turn, `via` is the list of a channel, all of them
+/// when not set. the ones that are down go last, a bad guess beats none
+fn order<'a>(conf: &'a Config, via: Option<&[String]>) -> Vec<&'a Proxy> {
+    let mut list: Vec<&Proxy> = match via {
+        Some(names) => names
+            .iter()
+            .filter_map(|n| conf.proxies.iter().find(|p| &p.name == n))
+            .collect(),
+        None => conf.proxies.iter().collect(),
+    };
+    // a stable sort keeps the config order within up and down
+    list.sort_by_key(|p| is_down(&p.name));
+    list
+}
+
+async fn execute(
+    p: &Proxy, rq: reqwest::Request,
+) -> reqwest::Result<reqwest::Response> {
+    match p.client.execute(rq).await {
+        Err(e) if e.is_connect() => {
+            failed(&p.name, &e);
+            Err(e)
+        }
+        Err(e) => Err(e),
+        Ok(v) => {
+            worked(&p.name);
+            Ok(v)
+        }
+    }
+}
+
+/// send a bot api request through the proxies until one connects, straight
+/// to telegram when there are none or `via` is empty. only a failure to
+/// connect moves on, after that the request may have been delivered
+pub async fn send(
+    conf: &Config, via: Option<&[String]>, rq: reqwest::Request,
+) -> reqwest::Result<reqwest::Response> {
+    let mut list = order(conf, via);
+    let Some(last) = list.pop() else {
+        return conf.tc.execute(rq).await;
+    };
+
+    for p in list {
+        // a streamed upload can only go out once, it gets no failover
+        let Some(copy) = rq.try_clone() else {
+            return execute(p, rq).await;
+        };
+        match execute(p, copy).await {
+            Err(e) if e.is_connect() => continue,
+            rs => return rs,
+        }
+    }
+
+    execute(last, rq).await
+}
+
+/// try the bot api through this proxy, marks it up or down
+pub async fn check(conf: &Config, p: &Proxy) -> Result<(), String> {
+    let rq = p.client.post(conf.tel_method("getMe")).build();
+    let rq = rq.map_err(|e| e.to_string())?;
+    match execute(p, rq).await {
+        Ok(rs) if rs.status().is_success() => Ok(()),
+        Ok(rs) => Err(format!("telegram answered {}", rs.status())),
+        Err(e) => Err(e.without_url().to_string()),
+    }
+}
+
+/// check every proxy once a minute, so a dead one is known before a
+/// message has to wait on it and a revived one is back in front
+pub fn watch() {
+    tokio::spawn(async {
+        let mut tick = tokio::time::interval(CHECK_EVERY);
+        loop {
+            tick.tick().await;
+            let conf = Config::get();
+            for p in &conf.proxies {
+                if let Err(e) = check(&conf, p).await {
+                    log::warn!("[proxy] check of {} failed: {e}", p.name);
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,65 @@
+//! secret config values, they can point at the environment, a file or a
+//! systemd credential and never show up in `Debug` output
+
+use std::path::PathBuf;
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret<T = String>(T);
+
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> std::fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("Secret(..)")
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Secret {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        d: D,
+    ) -> Result<Self, D::Error> {
+        let value = String::deserialize(d)?;
+        resolve(&value).map(Self).map_err(serde::de::Error::custom)
+    }
+}
+
+/// `env:NAME` reads an environment variable, `file:/path` a file and
+/// `cred:NAME` a credential from `LoadCredential=` of the systemd unit,
+/// `raw:` is cut off and the rest taken as is, so a value can start with
+/// one of these. anything else is the value itself
+pub fn resolve(value: &str) -> Result<String, String> {
+    if let Some(value) = value.strip_prefix("raw:") {
+        return Ok(value.to_string());
+    }
+    if let Some(name) = value.strip_prefix("env:") {
+        return std::env::var(name)
+            .map_err(|e| format!("environment variable {name}: {e}"));
+    }
+
+    let path = if let Some(path) = value.strip_prefix("file:") {
+        PathBuf::from(path)
+    } else if let Some(name) = value.strip_prefix("cred:") {
+        let Some(dir) = std::env::var_os("CREDENTIALS_DIRECTORY") else {
+            return Err(format!(
+                "credential {name}: CREDENTIALS_DIRECTORY is not set"
+            ));
+        };
+        PathBuf::from(dir).join(name)
+    } else {
+        return Ok(value.to_string());
+    };
+
+    match std::fs::read_to_string(&path) {
+        // editors and echo leave a newline at the end
+        Ok(v) => Ok(v.trim_end_matches(['\r', '\n']).to_string()),
+        Err(e) => Err(format!("secret file {path:?}: {e}")),
+    }
+}
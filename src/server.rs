@@ -0,0 +1,279 @@
+//! the sockets iris listens on, from the `[server]` section of the config
+
+use actix_web::dev::Extensions;
+use std::any::Any;
+use std::io::{Error, ErrorKind};
+use std::net::SocketAddr;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+
+const SOCKET: &str = "/usr/share/nginx/socks/iris.sock";
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+/// one of `tcp` or `unix`, the rest only goes with `unix`
+pub struct Listen {
+    /// e.g. `"127.0.0.1:7023"` or `"[::1]:7023"`
+    pub tcp: Option<SocketAddr>,
+    /// path of a unix socket, a stale one from a crash is removed
+    pub unix: Option<PathBuf>,
+    /// permissions of the socket file, e.g. `0o660`
+    pub mode: Option<u32>,
+    /// user name or uid to own the socket file
+    pub owner: Option<String>,
+    /// group name or gid of the socket file
+    pub group: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Server {
+    #[serde(default = "Server::default_listen")]
+    pub listen: Vec<Listen>,
+    /// worker threads, defaults to the number of cpus
+    pub workers: Option<usize>,
+    /// seconds an idle connection is kept open, 0 turns keep-alive off
+    pub keep_alive: Option<u64>,
+}
+
+impl Default for Server {
+    /// a local port while developing, the nginx socket in release, which
+    /// only iris and the `www-data` group of nginx can connect to
+    fn default() -> Self {
+        let listen = if cfg!(debug_assertions) {
+            Listen {
+                tcp: Some(SocketAddr::from(([127, 0, 0, 1], 7023))),
+                unix: None,
+                mode: None,
+                owner: None,
+                group: None,
+            }
+        } else {
+            Listen {
+                tcp: None,
+                unix: Some(PathBuf::from(SOCKET)),
+                mode: Some(0o660),
+                owner: None,
+                group: Some("www-data".to_string()),
+            }
+        };
+
+        Self { listen: vec![listen], workers: None, keep_alive: None }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// the process on the other end of a unix socket, from `SO_PEERCRED`
+pub struct PeerCred {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// keep the credentials of unix socket peers for [`crate::auth::Auth`],
+/// they are read with `HttpRequest::conn_data`. actix only calls this for
+/// sockets given with `listen_uds`, not `bind_uds`
+pub fn on_connect(conn: &dyn Any, ext: &mut Extensions) {
+    let Some(stream) = conn.downcast_ref::<actix_web::rt::net::UnixStream>()
+    else {
+        return;
+    };
+    match stream.peer_cred() {
+        Ok(c) => {
+            ext.insert(PeerCred { uid: c.uid(), gid: c.gid() });
+        }
+        Err(e) => log::warn!("[server] no peer credentials: {e}"),
+    }
+}
+
+/// a socket ready to be handed to the http server
+pub enum Bound {
+    Tcp(std::net::TcpListener),
+    Unix(UnixListener),
+}
+
+impl Server {
+    fn default_listen() -> Vec<Listen> {
+        Self::default().listen
+    }
+
+    pub fn check(&self) -> Result<(), String> {
+        if self.listen.is_empty() {
+            return Err("server.listen is empty".to_string());
+        }
+        if self.workers == Some(0) {
+            return Err("server.workers must be at least 1".to_string());
+        }
+
+        for l in &self.listen {
+            match (&l.tcp, &l.unix) {
+                (Some(_), None) => {
+                    if l.mode.is_some()
+                        || l.owner.is_some()
+                        || l.group.is_some()
+                    {
+                        return Err(format!(
+                            "server.listen {}: mode, owner and group are \
+                             only for unix sockets",
+                            l.name()
+                        ));
+                    }
+                }
+                (None, Some(_)) => {}
+                _ => {
+                    return Err(
+                        "server.listen needs one of tcp or unix".to_string()
+                    );
+                }
+            }
+            if l.mode.is_some_and(|v| v > 0o7777) {
+                return Err(format!("server.listen {}: bad mode", l.name()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// open every listener, in order
+    pub fn bind(&self) -> std::io::Result<Vec<Bound>> {
+        self.listen.iter().map(Listen::bind).collect()
+    }
+
+    /// where `iris send` finds this server, the first listener
+    pub fn url(&self) -> String {
+        match self.listen.first() {
+            Some(Listen { tcp: Some(addr), .. }) => format!("http://{addr}"),
+            Some(Listen { unix: Some(path), .. }) => {
+                format!("unix:{}", path.display())
+            }
+            _ => Self::default().url(),
+        }
+    }
+}
+
+impl Listen {
+    pub fn name(&self) -> String {
+        match (&self.tcp, &self.unix) {
+            (Some(addr), _) => addr.to_string(),
+            (_, Some(path)) => path.display().to_string(),
+            _ => "?".to_string(),
+        }
+    }
+
+    fn bind(&self) -> std::io::Result<Bound> {
+        let err =
+            |e: Error| Error::new(e.kind(), format!("{}: {e}", self.name()));
+        if let Some(addr) = self.tcp {
+            let listener = std::net::TcpListener::bind(addr).map_err(err)?;
+            log::info!("[server] listening on {addr}");
+            return Ok(Bound::Tcp(listener));
+        }
+
+        let path = self.unix.as_deref().expect("checked listener");
+        remove_stale(path).map_err(err)?;
+        let listener = UnixListener::bind(path).map_err(err)?;
+        if let Some(mode) = self.mode {
+            let perm = std::fs::Permissions::from_mode(mode);
+            std::fs::set_permissions(path, perm).map_err(err)?;
+        }
+        if self.owner.is_some() || self.group.is_some() {
+            let uid = self.owner.as_deref().map(uid).transpose();
+            let gid = self.group.as_deref().map(gid).transpose();
+            std::os::unix::fs::chown(
+                path,
+                uid.map_err(err)?,
+                gid.map_err(err)?,
+            )
+            .map_err(err)?;
+        }
+
+        log::info!("[server] listening on {}", path.display());
+        Ok(Bound::Unix(listener))
+    }
+}
+
+/// remove a socket file no process listens on anymore, it is left
+/// behind when iris does not shut down cleanly
+fn remove_stale(path: &Path) -> std::io::Result<()> {
+    let meta = match std::fs::symlink_metadata(path) {
+        Ok(v) => v,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if !meta.file_type().is_socket() {
+        return Err(Error::new(ErrorKind::AlreadyExists, "not a socket"));
+    }
+
+    match UnixStream::connect(path) {
+        Ok(_) => Err(Error::new(
+            ErrorKind::AddrInUse,
+            "another process is listening on it",
+        )),
+        Err(e) if e.kind() == ErrorKind::ConnectionRefused => {
+            log::warn!("[server] removing stale socket {}", path.display());
+            std::fs::remove_file(path)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// a user name or uid as a uid
+pub fn uid(user: &str) -> std::io::Result<u32> {
+    if let Ok(v) = user.parse() {
+        return Ok(v);
+    }
+    let name = std::ffi::CString::new(user)?;
+    let mut buf = vec![0; 16 * 1024];
+    // SAFETY: a zeroed passwd is valid, getpwnam_r fills it
+    let mut pw: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut found = std::ptr::null_mut();
+    // SAFETY: every pointer is valid for the call, the strings pw points
+    // to live in buf which outlives it
+    let rc = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut pw,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut found,
+        )
+    };
+    if rc != 0 {
+        return Err(Error::from_raw_os_error(rc));
+    }
+    if found.is_null() {
+        return Err(Error::new(ErrorKind::NotFound, format!("no user {user}")));
+    }
+    Ok(pw.pw_uid)
+}
+
+/// a group name or gid as a gid
+pub fn gid(group: &str) -> std::io::Result<u32> {
+    if let Ok(v) = group.parse() {
+        return Ok(v);
+    }
+    let name = std::ffi::CString::new(group)?;
+    let mut buf = vec![0; 16 * 1024];
+    // SAFETY: same as in uid
+    let mut gr: libc::group = unsafe { std::mem::zeroed() };
+    let mut found = std::ptr::null_mut();
+    let rc = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut gr,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut found,
+        )
+    };
+    if rc != 0 {
+        return Err(Error::from_raw_os_error(rc));
+    }
+    if found.is_null() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("no group {group}"),
+        ));
+    }
+    Ok(gr.gr_gid)
+}
@@ -0,0 +1,191 @@
+use super::{app, mock};
+use actix_web::test::{self, TestRequest};
+use serde_json::{Value, json};
+
+fn send(token: &str) -> actix_http::Request {
+    TestRequest::post()
+        .uri("/api/abzar/send/")
+        .insert_header(("authorization", format!("Bearer {token}")))
+        .set_json(json!({ "channel": "managed", "text": "hi" }))
+        .to_request()
+}
+
+#[actix_web::test]
+async fn token_lifecycle() {
+    let app = app().await;
+
+    let rq = TestRequest::post()
+        .uri("/api/admin/tokens/")
+        .insert_header(("authorization", "Bearer admin"))
+        .set_json(json!({ "name": "svc", "channels": ["managed"] }))
+        .to_request();
+    let rs = test::call_service(&app, rq).await;
+    assert_eq!(rs.status(), 200);
+    let body: Value = test::read_body_json(rs).await;
+    let token = body["token"].as_str().unwrap().to_string();
+    let id = body["info"]["id"].as_i64().unwrap();
+    assert!(token.starts_with(&format!("iris_{id}_")));
+    assert!(body["info"]["expires_at"].is_i64());
+    assert!(body["info"].get("hash").is_none());
+
+    let rs = test::call_service(&app, send(&token)).await;
+    assert_eq!(rs.status(), 200);
+    assert_eq!(mock::calls("-1019").len(), 1);
+
+    let rq = TestRequest::get()
+        .uri("/api/admin/tokens/")
+        .insert_header(("authorization", "Bearer admin"))
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, rq).await;
+    assert_eq!(body[0]["name"], "svc");
+    assert!(body[0]["last_used"].is_i64());
+
+    let rq = TestRequest::post()
+        .uri(&format!("/api/admin/tokens/{id}/rotate/"))
+        .insert_header(("authorization", "Bearer admin"))
+        .set_json(json!({ "expires_in": 0 }))
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, rq).await;
+    let rotated = body["token"].as_str().unwrap().to_string();
+    assert!(body["info"]["expires_at"].is_null());
+
+    let rs = test::call_service(&app, send(&token)).await;
+    assert_eq!(rs.status(), 403);
+    let rs = test::call_service(&app, send(&rotated)).await;
+    assert_eq!(rs.status(), 200);
+
+    let rq = TestRequest::delete()
+        .uri(&format!("/api/admin/tokens/{id}/"))
+        .insert_header(("authorization", "Bearer admin"))
+        .to_request();
+    let rs = test::call_service(&app, rq).await;
+    assert_eq!(rs.status(), 200);
+
+    let rs = test::call_service(&app, send(&rotated)).await;
+    assert_eq!(rs.status(), 403);
+    assert_eq!(mock::calls("-1019").len(), 2);
+}
+
+#[actix_web::test]
+async fn admin_only() {
+    let app = app().await;
+
+    // a token without ops can do everything but admin
+    for token in ["token", "scoped", "nope"] {
+        let rq = TestRequest::get()
+            .uri("/api/admin/tokens/")
+            .insert_header(("authorization", format!("Bearer {token}")))
+            .to_request();
+        let rs = test::call_service(&app, rq).await;
+        assert_eq!(rs.status(), 403);
+    }
+
+    let rq = TestRequest::get().uri("/api/admin/tokens/").to_request();
+    let rs = test::call_service(&app, rq).await;
+    assert_eq!(rs.status(), 403);
+
+    for body in [
+        json!({ "name": "test" }),
+        json!({ "name": "x", "channels": ["nope"] }),
+        json!({ "name": "x", "expires_in": -1 }),
+    ] {
+        let rq = TestRequest::post()
+            .uri("/api/admin/tokens/")
+            .insert_header(("authorization", "Bearer admin"))
+            .set_json(body)
+            .to_request();
+        let rs = test::call_service(&app, rq).await;
+        assert_eq!(rs.status(), 400);
+    }
+}
+
+#[actix_web::test]
+async fn audit_log() {
+    let app = app().await;
+
+    let rq = TestRequest::post()
+        .uri("/api/abzar/send/")
+        .insert_header(("authorization", "Bearer token"))
+        .set_json(json!({ "channel": "audited", "text": "hello" }))
+        .to_request();
+    let rs = test::call_service(&app, rq).await;
+    assert_eq!(rs.status(), 200);
+
+    let rq = TestRequest::post()
+        .uri("/api/abzar/send/")
+        .set_json(json!({ "channel": "audited", "pass": "bad", "text": "x" }))
+        .to_request();
+    let rs = test::call_service(&app, rq).await;
+    assert_eq!(rs.status(), 404);
+
+    let rq = TestRequest::get()
+        .uri("/api/admin/audit/?channel=audited&order=asc")
+        .insert_header(("authorization", "Bearer admin"))
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, rq).await;
+    let entries = body.as_array().unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0]["endpoint"], "/api/abzar/send/");
+    assert_eq!(entries[0]["identity"], "token:test");
+    assert_eq!(entries[0]["status"], 200);
+    assert_eq!(entries[0]["text_preview"], "hello");
+    assert_eq!(entries[0]["text_hash"].as_str().unwrap().len(), 64);
+    assert!(entries[0]["message_id"].is_i64());
+    assert!(entries[0]["error"].is_null());
+    assert_eq!(entries[1]["identity"], "pass");
+    assert_eq!(entries[1]["status"], 404);
+    assert_eq!(entries[1]["error"], "not_found");
+
+    let rq = TestRequest::get()
+        .uri("/api/admin/audit/?channel=audited&failed=true")
+        .insert_header(("authorization", "Bearer admin"))
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, rq).await;
+    assert_eq!(body.as_array().unwrap().len(), 1);
+
+    let rq = TestRequest::get()
+        .uri("/api/admin/audit/")
+        .insert_header(("authorization", "Bearer token"))
+        .to_request();
+    let rs = test::call_service(&app, rq).await;
+    assert_eq!(rs.status(), 403);
+}
+
+#[actix_web::test]
+async fn token_limit() {
+    let app = app().await;
+
+    let rq = TestRequest::post()
+        .uri("/api/admin/tokens/")
+        .insert_header(("authorization", "Bearer admin"))
+        .set_json(json!({
+            "name": "limited",
+            "channels": ["managed_limit"],
+            "limit": { "daily_messages": 1 },
+        }))
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, rq).await;
+    assert_eq!(body["info"]["limit"]["daily_messages"], 1);
+    let token = body["token"].as_str().unwrap().to_string();
+
+    let send = || {
+        TestRequest::post()
+            .uri("/api/abzar/send/")
+            .insert_header(("authorization", format!("Bearer {token}")))
+            .set_json(json!({ "channel": "managed_limit", "text": "hi" }))
+            .to_request()
+    };
+    let rs = test::call_service(&app, send()).await;
+    assert_eq!(rs.status(), 200);
+    let rs = test::call_service(&app, send()).await;
+    assert_eq!(rs.status(), 429);
+    assert_eq!(mock::calls("-1031").len(), 1);
+
+    // a config token given the same name later is someone else
+    let rq = TestRequest::get()
+        .uri("/api/admin/audit/?channel=managed_limit&order=asc")
+        .insert_header(("authorization", "Bearer admin"))
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, rq).await;
+    assert_eq!(body[0]["identity"], "api:limited");
+}
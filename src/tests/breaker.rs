@@ -0,0 +1,74 @@
+use super::mock;
+use crate::breaker;
+use crate::config::Config;
+use serde_json::json;
+
+#[actix_web::test]
+async fn outcome() {
+    super::setup();
+    let conf = Config::get();
+    let key = "test:outcome";
+
+    // an upload that broke on our side does not count against the backend
+    for _ in 0..3 {
+        let body = futures_util::stream::iter([Err::<Vec<u8>, _>(
+            std::io::Error::other("aborted"),
+        )]);
+        let rq = conf.tc.post(mock::url());
+        let r = rq.body(reqwest::Body::wrap_stream(body)).send().await;
+        assert!(r.is_err());
+        breaker::outcome(&conf, key, &r);
+    }
+    assert!(breaker::admit(key).is_ok());
+
+    // neither does flood control of a single chat
+    let url = format!("{}/bot{}/sendMessage", mock::url(), mock::TOKEN);
+    for _ in 0..3 {
+        mock::rate_limit("-1035", 5);
+        let rq = conf.tc.post(&url).json(&json!({ "chat_id": "-1035" }));
+        let r = rq.send().await;
+        assert_eq!(r.as_ref().unwrap().status(), 429);
+        breaker::outcome(&conf, key, &r);
+    }
+    assert!(breaker::admit(key).is_ok());
+
+    // nothing listening does
+    for _ in 0..2 {
+        let r = conf.tc.get("http://127.0.0.1:1").send().await;
+        breaker::outcome(&conf, key, &r);
+    }
+    assert!(breaker::admit(key).is_err());
+}
+
+#[test]
+fn half_open() {
+    let conf = Config::parse(
+        "tel_token = \"T\"\n[breaker]\nfailures = 1\nopen_for = 0\n[channels]",
+    )
+    .unwrap();
+    let key = "test:half_open";
+
+    breaker::record(&conf, key, false);
+    // only one probe goes out, the rest wait for it
+    let probe = breaker::admit(key).unwrap();
+    let e = breaker::admit(key).unwrap_err();
+    assert_eq!(e.code(), crate::ErrorCode::CircuitOpen);
+    assert_eq!(e.wait(), Some(1));
+
+    // one that never called the backend hands it on
+    drop(probe);
+    let probe = breaker::admit(key).unwrap();
+    assert!(breaker::admit(key).is_err());
+
+    // a failed probe opens it again, the next one gets to probe
+    breaker::record(&conf, key, false);
+    drop(probe);
+    let probe = breaker::admit(key).unwrap();
+    assert!(breaker::admit(key).is_err());
+
+    // a good one closes it
+    breaker::record(&conf, key, true);
+    drop(probe);
+    assert!(breaker::admit(key).is_ok());
+    assert!(breaker::admit(key).is_ok());
+}
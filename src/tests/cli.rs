@@ -0,0 +1,193 @@
+use super::mock;
+use crate::config::Config;
+use serde_json::json;
+
+#[actix_web::test]
+async fn check_config() {
+    let conf = Config::parse(&format!(
+        r#"
+        tel_token = "TEST"
+        tel_api = "{}"
+
+        [channels]
+        a_ok = {{ chat = "-1101", thread = "5" }}
+        b_missing = {{ chat = "-1102" }}
+        c_muted = {{ chat = "-1103" }}
+        d_thread = {{ chat = "-1104", thread = "9" }}
+        e_push = {{ ntfy = {{ url = "http://127.0.0.1:9", topic = "t" }} }}
+        "#,
+        mock::url()
+    ))
+    .unwrap();
+
+    mock::fail("-1102", 400, "Bad Request: chat not found");
+    // replies go to the calls for a chat in order, getChat comes first
+    let group = json!({ "ok": true, "result": { "type": "group" } });
+    mock::script("-1103", 200, group);
+    mock::script(
+        "-1103",
+        200,
+        json!({ "ok": true, "result": { "status": "restricted" } }),
+    );
+    mock::script(
+        "-1104",
+        200,
+        json!({ "ok": true, "result": { "type": "supergroup", "title": "T" } }),
+    );
+
+    let report = crate::cli::check(&conf, true).await;
+    let names: Vec<_> = report.iter().map(|(v, _)| v.as_str()).collect();
+    assert_eq!(
+        names,
+        [
+            "bot",
+            "channel a_ok",
+            "channel b_missing",
+            "channel c_muted",
+            "channel d_thread",
+            "channel e_push"
+        ]
+    );
+
+    assert_eq!(report[0].1, Ok("@iris_test_bot".to_string()));
+    assert_eq!(report[1].1, Ok("Test (supergroup), thread 5".to_string()));
+    let err = |i: usize| report[i].1.clone().unwrap_err();
+    assert!(err(2).contains("chat not found"), "{}", err(2));
+    assert!(err(3).contains("can not post"), "{}", err(3));
+    assert!(err(4).contains("topics are off"), "{}", err(4));
+    assert!(report[5].1.is_ok());
+
+    let calls = mock::calls("-1101");
+    let methods: Vec<_> = calls.iter().map(|v| v.method.as_str()).collect();
+    assert_eq!(methods, ["getChat", "getChatMember", "sendChatAction"]);
+    assert_eq!(calls[1].body["user_id"], mock::BOT_ID);
+    assert_eq!(calls[2].body["message_thread_id"], "5");
+
+    // without the probe nothing shows up in the threads
+    let report = crate::cli::check(&conf, false).await;
+    assert_eq!(
+        report[1].1,
+        Ok("Test (supergroup), thread 5 not probed, see --probe-threads"
+            .to_string())
+    );
+    let calls = mock::calls("-1101");
+    let methods: Vec<_> = calls.iter().map(|v| v.method.as_str()).collect();
+    assert_eq!(methods[3..], ["getChat", "getChatMember"], "{methods:?}");
+}
+
+#[test]
+fn send_args() {
+    use crate::cli::Command;
+    let parse =
+        |v: &str| Command::parse_from(v.split_whitespace().map(String::from));
+
+    let Ok(Some(Command::Send(args))) = parse(
+        "-c iris.toml send --channel ops --text hi --parse-mode html \
+         --token t --direct",
+    ) else {
+        panic!("send was not parsed");
+    };
+    assert_eq!(args.channel, "ops");
+    assert_eq!(args.text.as_deref(), Some("hi"));
+    assert_eq!(args.parse_mode.as_deref(), Some("html"));
+    assert_eq!(args.token.as_deref(), Some("t"));
+    assert!(args.direct && args.file.is_none() && args.url.is_none());
+
+    assert!(matches!(parse("-c iris.toml"), Ok(None)));
+    assert!(matches!(
+        parse("check-config -c iris.toml"),
+        Ok(Some(Command::CheckConfig { probe_threads: false }))
+    ));
+    assert!(matches!(
+        parse("check-config --probe-threads"),
+        Ok(Some(Command::CheckConfig { probe_threads: true }))
+    ));
+    assert!(parse("check-config --bad").is_err());
+    assert!(parse("send --text hi").err().unwrap().contains("--channel"));
+    assert!(parse("send --channel ops --bad").err().unwrap().contains("--bad"));
+    assert!(parse("send --channel ops --url").is_err());
+}
+
+#[actix_web::test]
+async fn send() {
+    use crate::cli::SendArgs;
+    use actix_web::{App, HttpServer};
+
+    super::setup();
+    let state = super::state().await;
+    let server = HttpServer::new(move || {
+        App::new().app_data(state.clone()).configure(crate::config_app)
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+    let url = format!("http://{}", server.addrs()[0]);
+    actix_web::rt::spawn(server.run());
+
+    let args = |text: &str| SendArgs {
+        channel: "cli".to_string(),
+        text: Some(text.to_string()),
+        pass: Some("pass".to_string()),
+        url: Some(url.clone()),
+        ..Default::default()
+    };
+
+    let id = crate::cli::send(SendArgs {
+        parse_mode: Some("markdownv2".to_string()),
+        severity: Some("ERROR".to_string()),
+        ..args("over http")
+    })
+    .await
+    .unwrap();
+    assert!(id.is_some());
+
+    let file = super::local_dir().join("cli.txt");
+    std::fs::write(&file, "attached").unwrap();
+    crate::cli::send(SendArgs { file: Some(file.clone()), ..args("a file") })
+        .await
+        .unwrap();
+
+    let e = crate::cli::send(SendArgs { pass: None, ..args("no pass") })
+        .await
+        .unwrap_err();
+    assert!(e.contains("401") || e.contains("403"), "{e}");
+
+    let e = crate::cli::send(SendArgs {
+        parse_mode: Some("rtf".to_string()),
+        ..args("bad mode")
+    })
+    .await
+    .unwrap_err();
+    assert!(e.contains("rtf is not one of"), "{e}");
+
+    crate::cli::send(SendArgs {
+        channel: "cli".to_string(),
+        text: Some("direct".to_string()),
+        file: Some(file.clone()),
+        direct: true,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+
+    // mail_down takes attachments up to 10 bytes
+    std::fs::write(&file, "more than ten bytes").unwrap();
+    let e = crate::cli::send(SendArgs {
+        channel: "mail_down".to_string(),
+        text: Some("too big".to_string()),
+        file: Some(file),
+        direct: true,
+        ..Default::default()
+    })
+    .await
+    .unwrap_err();
+    assert!(e.contains("max file size is 10 bytes"), "{e}");
+
+    let calls = mock::calls("-1025");
+    let methods: Vec<_> = calls.iter().map(|v| v.method.as_str()).collect();
+    assert_eq!(methods, ["sendMessage", "sendDocument", "sendDocument"]);
+    assert_eq!(calls[0].body["parse_mode"], "MarkdownV2");
+    assert_eq!(calls[1].files["document"].data, b"attached");
+    assert_eq!(calls[1].files["document"].name.as_deref(), Some("cli.txt"));
+    assert_eq!(calls[2].files["document"].data, b"attached");
+}
@@ -0,0 +1,140 @@
+use crate::config::Config;
+
+const BASE: &str = r#"
+tel_token = "TEST"
+
+[channels]
+kept = { chat = "-1" }
+edited = { chat = "-2" }
+gone = { chat = "-3" }
+"#;
+
+#[test]
+fn reload_diff() {
+    let old = Config::parse(BASE).unwrap();
+    let new = Config::parse(
+        &BASE
+            .replace(
+                r#"edited = { chat = "-2" }"#,
+                r#"edited = { chat = "-9" }"#,
+            )
+            .replace(r#"gone = { chat = "-3" }"#, r#"new = { chat = "-4" }"#),
+    )
+    .unwrap();
+
+    assert_eq!(
+        Config::diff(&old, &new),
+        r#"added: ["new"], removed: ["gone"], changed: ["edited"]"#
+    );
+    assert_eq!(
+        Config::diff(&old, &Config::parse(BASE).unwrap()),
+        "added: [], removed: [], changed: []"
+    );
+
+    // the same table with a secret file that changed in between
+    let path = super::local_dir().join("reload-pass");
+    let conf = format!(
+        "tel_token = \"T\"\n[channels]\nx = {{ chat = \"-1\", pass = \"file:{}\" }}",
+        path.display()
+    );
+    std::fs::create_dir_all(super::local_dir()).unwrap();
+    std::fs::write(&path, "old").unwrap();
+    let old = Config::parse(&conf).unwrap();
+    std::fs::write(&path, "new").unwrap();
+    let new = Config::parse(&conf).unwrap();
+    assert_eq!(
+        Config::diff(&old, &new),
+        r#"added: [], removed: [], changed: ["x"]"#
+    );
+}
+
+#[test]
+fn invalid_configs() {
+    for (bad, why) in [
+        ("[channels]\nx = { chat = \"-1\" }", "tel_token"),
+        (
+            "tel_token = \"T\"\n[channels]\nx = { ntfy = { url = \"u\", topic = \"t\" } }",
+            "bad url",
+        ),
+        (
+            "tel_token = \"T\"\n[channels]\nx = { chat = \"-1\", allow_ips = [\"nope\"] }",
+            "invalid ip range",
+        ),
+        (
+            "tel_token = \"T\"\n[channels]\n[tokens]\nt = { token = \"t\", channels = [\"x\"] }",
+            "unknown channel x",
+        ),
+        (
+            "tel_token = \"T\"\n[channels]\n[tokens]\nt = { token = \"$2b$04$EpsGoU6ZoVvXgNwXc3T1dOQ3hX5yT9sSDC7V4rbDR1EbUCuCTHTka\" }",
+            "needs a prefix",
+        ),
+        (
+            "tel_token = \"T\"\ntrusted_peers = { users = [\"no-such-user-here\"] }\n[channels]",
+            "trusted_peers: no user",
+        ),
+        (
+            "tel_token = \"T\"\n[channels]\n[tokens]\nt = { token = \"iris_1_x\" }",
+            "iris_ is for api tokens",
+        ),
+        (
+            "tel_token = \"T\"\n[channels]\n[tokens]\na = { token = \"a\", prefix = \"ci\" }\nb = { token = \"b\", prefix = \"ci_\" }",
+            "overlaps",
+        ),
+        (
+            "tel_token = \"T\"\nproxies = [{ name = \"p\", url = \"nope\" }]\n[channels]",
+            "proxy p",
+        ),
+        (
+            "tel_token = \"T\"\nproxies = [{ name = \"p\", url = \"socks5://h:1\" }, { name = \"p\", url = \"socks5://h:2\" }]\n[channels]",
+            "used twice",
+        ),
+        (
+            "tel_token = \"T\"\n[channels]\nx = { chat = \"-1\", proxies = [\"p\"] }",
+            "unknown proxy p",
+        ),
+        (
+            "tel_token = \"T\"\n[channels]\nx = { ntfy = { url = \"http://n\", topic = \"t\" }, proxies = [] }",
+            "proxies are for telegram",
+        ),
+        (
+            "tel_token = \"T\"\n[channels]\nx = { chat = \"-1\", fallback = \"x\" }",
+            "bad fallback x",
+        ),
+        (
+            "tel_token = \"T\"\n[breaker]\nfailures = 0\n[channels]",
+            "at least 1",
+        ),
+        (
+            "tel_token = \"T\"\n[log]\nfilter = \"iris=loud\"\n[channels]",
+            "log.filter: bad level",
+        ),
+        (
+            "tel_token = \"T\"\n[log]\nformat = \"xml\"\n[channels]",
+            "unknown variant",
+        ),
+    ] {
+        let Err(e) = Config::parse(bad) else {
+            panic!("{bad} was accepted");
+        };
+        assert!(e.contains(why), "{e}");
+    }
+}
+
+#[actix_web::test]
+async fn secrets() {
+    use crate::secret::resolve;
+    assert_eq!(resolve("plain").unwrap(), "plain");
+    assert_eq!(
+        resolve("raw:env:NOT_A_VARIABLE").unwrap(),
+        "env:NOT_A_VARIABLE"
+    );
+    assert_eq!(resolve("raw:raw:x").unwrap(), "raw:x");
+    assert!(resolve("env:IRIS_TEST_NOT_SET").is_err());
+
+    let conf = Config::parse(
+        "tel_token = \"T\"\n[channels]\nx = { chat = \"-1\", pass = \"raw:cred:x\" }",
+    )
+    .unwrap();
+    let pass = conf.channels["x"].pass.as_ref().unwrap();
+    assert!(pass.verify("cred:x").await);
+}
@@ -0,0 +1,62 @@
+use crate::delivery::email::{body, strip_html, subject};
+use crate::delivery::{Message, ParseMode, Severity};
+
+fn msg(text: &str, parse_mode: Option<ParseMode>) -> Message<'_> {
+    Message { text, parse_mode, severity: None, document: None }
+}
+
+/// the mail as text, with the quoted-printable line breaks and `=` undone
+fn formatted(mp: lettre::message::MultiPart) -> String {
+    let text = String::from_utf8(mp.formatted()).unwrap();
+    text.replace("=\r\n", "").replace("=3D", "=")
+}
+
+#[test]
+fn strip() {
+    assert_eq!(
+        strip_html("<b>disk</b> <a href=\"x\">full</a> on <code>db1</code>"),
+        "disk full on db1"
+    );
+    assert_eq!(
+        strip_html("a &lt;b&gt; &amp;&amp; &quot;c&quot;"),
+        "a <b> && \"c\""
+    );
+    // entities are decoded once, an escaped one stays escaped
+    assert_eq!(strip_html("&amp;lt;"), "&lt;");
+    assert_eq!(strip_html("1 > 0"), "1 > 0");
+}
+
+#[test]
+fn bodies() {
+    let html =
+        formatted(body(&msg("<b>up</b> &amp; running", Some(ParseMode::Html))));
+    assert!(html.contains("multipart/alternative"), "{html}");
+    assert!(html.contains("Content-Type: text/plain"), "{html}");
+    assert!(html.contains("\r\nup & running"), "{html}");
+    assert!(html.contains("Content-Type: text/html"), "{html}");
+    assert!(html.contains("<body><b>up</b> &amp; running</body>"), "{html}");
+
+    // anything else is kept as it is and escaped for the html part
+    let plain =
+        formatted(body(&msg("a <tag> & *bold*", Some(ParseMode::MarkdownV2))));
+    assert!(plain.contains("\r\na <tag> & *bold*"), "{plain}");
+    assert!(
+        plain.contains(
+            "<pre style=\"white-space: pre-wrap\">a &lt;tag&gt; &amp; *bold*</pre>"
+        ),
+        "{plain}"
+    );
+}
+
+#[test]
+fn subjects() {
+    let m = msg("  <b>disk full</b>  \nsecond line", Some(ParseMode::Html));
+    assert_eq!(subject(None, &m), "disk full");
+    assert_eq!(subject(Some("audit"), &m), "audit");
+    assert_eq!(subject(None, &msg("", None)), "iris");
+    assert_eq!(subject(None, &msg(&"x".repeat(100), None)).len(), 78);
+
+    let m = Message { severity: Some(Severity::Critical), ..msg("down", None) };
+    assert_eq!(subject(None, &m), "[critical] down");
+    assert_eq!(subject(Some("audit"), &m), "[critical] audit");
+}
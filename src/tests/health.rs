@@ -0,0 +1,47 @@
+use crate::config::Config;
+use crate::health;
+use actix_web::App;
+use actix_web::test::{self, TestRequest};
+use serde_json::Value;
+
+#[actix_web::test]
+async fn health() {
+    super::setup();
+    let state = super::state().await;
+    let app = test::init_service(
+        App::new().app_data(state.clone()).configure(crate::config_app),
+    )
+    .await;
+
+    let rq = TestRequest::get().uri("/healthz").to_request();
+    let rs: Value = test::call_and_read_body_json(&app, rq).await;
+    assert_eq!(rs["status"], "ok");
+
+    let rq = TestRequest::get().uri("/readyz").to_request();
+    let rs = test::call_service(&app, rq).await;
+    assert_eq!(rs.status(), 200);
+    let rs: Value = test::read_body_json(rs).await;
+    assert_eq!(rs["status"], "ready");
+    assert_eq!(rs["checks"]["telegram"]["detail"], "@iris_test_bot");
+    for check in ["config", "database", "telegram"] {
+        assert_eq!(rs["checks"][check]["ok"], true, "{check}: {rs}");
+    }
+
+    // telegram only behind a proxy that is gone
+    let conf = Config::parse(
+        r#"
+        tel_token = "TEST"
+        tel_api = "http://127.0.0.4:1"
+        proxies = [{ name = "hz_dead", url = "http://127.0.0.1:1" }]
+
+        [channels]
+        "#,
+    )
+    .unwrap();
+    let ready = health::ready(&conf, &state).await;
+    assert_eq!(ready.status, "not_ready");
+    assert!(!ready.checks["telegram"].ok);
+    assert!(ready.checks["database"].ok);
+    assert!(!ready.proxies["hz_dead"].ok);
+    assert!(!ready.checks["telegram"].detail.contains("botTEST"));
+}
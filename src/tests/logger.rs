@@ -0,0 +1,54 @@
+use crate::logger::{self, Filter};
+use log::{Level, LevelFilter};
+
+#[test]
+fn filter() {
+    let f = Filter::parse("info, iris::proxy=trace,iris=warn").unwrap();
+    assert_eq!(f.level("iris::proxy"), LevelFilter::Trace);
+    assert_eq!(f.level("iris::proxy::x"), LevelFilter::Trace);
+    assert_eq!(f.level("iris::proxyless"), LevelFilter::Warn);
+    assert_eq!(f.level("iris"), LevelFilter::Warn);
+    assert_eq!(f.level("actix_web"), LevelFilter::Info);
+    // the loud ones stay off unless they are named
+    assert_eq!(f.level("sqlx::query"), LevelFilter::Off);
+    let f = Filter::parse("sqlx=warn").unwrap();
+    assert_eq!(f.level("sqlx::query"), LevelFilter::Warn);
+    assert_eq!(f.level("iris"), LevelFilter::Debug);
+
+    for bad in ["loud", "iris=loud", "=info"] {
+        assert!(Filter::parse(bad).is_err(), "{bad} was accepted");
+    }
+}
+
+#[test]
+fn format() {
+    assert_eq!(logger::rfc3339(0, 0), "1970-01-01T00:00:00.000Z");
+    assert_eq!(logger::rfc3339(1_709_210_096, 7), "2024-02-29T12:34:56.007Z");
+    assert_eq!(logger::rfc3339(-1, 0), "1969-12-31T23:59:59.000Z");
+
+    let args = format_args!("sent \"hi\"");
+    let record = log::Record::builder()
+        .level(Level::Warn)
+        .target("iris::proxy")
+        .line(Some(7))
+        .args(args)
+        .build();
+    let ts = "2024-02-29T12:34:56.007Z";
+
+    let text = logger::text(&record, ts, false);
+    assert_eq!(
+        text,
+        "{2024-02-29T12:34:56.007Z}[W]{iris::proxy:7}: sent \"hi\""
+    );
+    assert!(logger::text(&record, ts, true).contains("\x1b[33m"));
+
+    let json: serde_json::Value =
+        serde_json::from_str(&logger::json(&record, ts)).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!({
+            "ts": ts, "level": "warn", "target": "iris::proxy",
+            "line": 7, "msg": "sent \"hi\"",
+        })
+    );
+}
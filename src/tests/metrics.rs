@@ -0,0 +1,49 @@
+use actix_web::test::{self, TestRequest};
+use actix_web::{App, middleware::from_fn};
+use serde_json::json;
+
+#[actix_web::test]
+async fn metrics() {
+    super::setup();
+    let app = test::init_service(
+        App::new()
+            .wrap(from_fn(crate::metrics::record))
+            .app_data(super::state().await)
+            .configure(crate::config_app),
+    )
+    .await;
+
+    let send = |pass: &str| {
+        TestRequest::post()
+            .uri("/api/abzar/send/")
+            .set_json(
+                json!({ "channel": "metrics", "pass": pass, "text": "x" }),
+            )
+            .to_request()
+    };
+    assert_eq!(test::call_service(&app, send("pass")).await.status(), 200);
+    assert_eq!(test::call_service(&app, send("bad")).await.status(), 404);
+
+    let rq = TestRequest::get().uri("/metrics").to_request();
+    let rs = test::call_service(&app, rq).await;
+    assert_eq!(rs.status(), 200);
+    let ct = rs.headers().get("content-type").unwrap().to_str().unwrap();
+    assert!(ct.starts_with("text/plain; version=0.0.4"), "{ct}");
+    let body = test::read_body(rs).await;
+    let body = std::str::from_utf8(&body).unwrap();
+
+    for line in [
+        "# TYPE iris_requests_total counter",
+        "iris_requests_total{endpoint=\"/api/abzar/send/\",\
+         channel=\"metrics\",status=\"200\"} 1",
+        "iris_requests_total{endpoint=\"/api/abzar/send/\",\
+         channel=\"metrics\",status=\"404\"} 1",
+        "# TYPE iris_request_duration_seconds histogram",
+        "iris_request_duration_seconds_bucket{\
+         endpoint=\"/api/abzar/send/\",le=\"+Inf\"}",
+        "iris_telegram_duration_seconds_count{method=\"sendMessage\"}",
+        "# TYPE iris_deliveries_in_flight gauge",
+    ] {
+        assert!(body.contains(line), "{line} is missing from\n{body}");
+    }
+}
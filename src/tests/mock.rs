@@ -0,0 +1,235 @@
+//! an in-process stand-in for the telegram bot api that records every call,
+//! it also takes ntfy pushes at `/ntfy/{topic}`
+
+use actix_multipart::Multipart;
+use actix_web::{App, HttpRequest, HttpResponse, HttpServer, post, route, web};
+use futures_util::StreamExt;
+use serde_json::{Map, Value, json};
+use std::collections::{HashMap, VecDeque};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Mutex, OnceLock};
+
+pub const TOKEN: &str = "TEST";
+/// user id of the bot, as given by `getMe`
+pub const BOT_ID: i64 = 1000;
+
+#[derive(Debug, Clone)]
+pub struct File {
+    pub name: Option<String>,
+    pub mime: Option<String>,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+/// a recorded bot api call, multipart text fields end up in `body` as strings
+pub struct Call {
+    pub method: String,
+    pub body: Map<String, Value>,
+    pub files: HashMap<String, File>,
+}
+
+struct Reply {
+    status: u16,
+    body: Value,
+}
+
+#[derive(Default)]
+struct State {
+    calls: Vec<Call>,
+    script: HashMap<String, VecDeque<Reply>>,
+    message_id: i64,
+}
+
+struct Mock {
+    addr: SocketAddr,
+    state: Mutex<State>,
+}
+
+fn mock() -> &'static Mock {
+    static MOCK: OnceLock<Mock> = OnceLock::new();
+    MOCK.get_or_init(|| {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("mock bind");
+        let addr = listener.local_addr().expect("mock addr");
+        std::thread::spawn(move || {
+            actix_web::rt::System::new().block_on(async move {
+                HttpServer::new(|| App::new().service(bot).service(ntfy))
+                    .workers(1)
+                    .listen(listener)
+                    .expect("mock listen")
+                    .run()
+                    .await
+            })
+        });
+        Mock { addr, state: Mutex::default() }
+    })
+}
+
+/// base url to use as `tel_api`
+pub fn url() -> String {
+    format!("http://{}", mock().addr)
+}
+
+/// every call made for the given chat
+pub fn calls(chat: &str) -> Vec<Call> {
+    let state = mock().state.lock().unwrap();
+    state
+        .calls
+        .iter()
+        .filter(|c| {
+            c.body.get("chat_id").and_then(|v| v.as_str()) == Some(chat)
+        })
+        .cloned()
+        .collect()
+}
+
+/// make the next call for the chat answer with the given status and body
+pub fn script(chat: &str, status: u16, body: Value) {
+    let mut state = mock().state.lock().unwrap();
+    state
+        .script
+        .entry(chat.to_string())
+        .or_default()
+        .push_back(Reply { status, body });
+}
+
+pub fn fail(chat: &str, status: u16, description: &str) {
+    let body = json!({
+        "ok": false, "error_code": status, "description": description
+    });
+    script(chat, status, body);
+}
+
+pub fn rate_limit(chat: &str, retry_after: u64) {
+    let body = json!({
+        "ok": false, "error_code": 429,
+        "description": format!("Too Many Requests: retry after {retry_after}"),
+        "parameters": { "retry_after": retry_after }
+    });
+    script(chat, 429, body);
+}
+
+async fn read_multipart(
+    rq: &HttpRequest, payload: web::Payload,
+) -> (Map<String, Value>, HashMap<String, File>) {
+    let mut body = Map::new();
+    let mut files = HashMap::new();
+    let mut mp = Multipart::new(rq.headers(), payload);
+
+    while let Some(field) = mp.next().await {
+        let mut field = field.expect("mock multipart field");
+        let cd = field.content_disposition().cloned();
+        let name = cd
+            .as_ref()
+            .and_then(|v| v.get_name())
+            .unwrap_or_default()
+            .to_string();
+        let fname =
+            cd.as_ref().and_then(|v| v.get_filename()).map(String::from);
+        let mime = field.content_type().map(|v| v.to_string());
+
+        let mut data = Vec::new();
+        while let Some(chunk) = field.next().await {
+            data.extend_from_slice(&chunk.expect("mock multipart chunk"));
+        }
+
+        if fname.is_some() {
+            files.insert(name, File { name: fname, mime, data });
+        } else {
+            let text = String::from_utf8(data).expect("mock utf8 field");
+            body.insert(name, Value::String(text));
+        }
+    }
+
+    (body, files)
+}
+
+#[post("/bot{token}/{method}")]
+async fn bot(
+    rq: HttpRequest, path: web::Path<(String, String)>, payload: web::Payload,
+) -> HttpResponse {
+    let (token, method) = path.into_inner();
+    if token != TOKEN {
+        return HttpResponse::Unauthorized().json(json!({
+            "ok": false, "error_code": 401, "description": "Unauthorized"
+        }));
+    }
+
+    let ct = rq
+        .headers()
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+
+    let (body, files) = if ct.starts_with("multipart/form-data") {
+        read_multipart(&rq, payload).await
+    } else {
+        let data = payload.to_bytes().await.expect("mock body");
+        let body = match serde_json::from_slice(&data) {
+            Ok(Value::Object(v)) => v,
+            _ => Map::new(),
+        };
+        (body, HashMap::new())
+    };
+
+    let chat = body.get("chat_id").and_then(|v| v.as_str()).map(String::from);
+    let mut state = mock().state.lock().unwrap();
+    state.calls.push(Call { method: method.clone(), body, files });
+
+    let reply =
+        chat.as_ref().and_then(|c| state.script.get_mut(c)?.pop_front());
+    if let Some(r) = reply {
+        let status = actix_web::http::StatusCode::from_u16(r.status).unwrap();
+        return HttpResponse::build(status).json(r.body);
+    }
+
+    let result = match method.as_str() {
+        "deleteMessage" | "sendChatAction" => json!(true),
+        "getMe" => json!({
+            "id": BOT_ID, "is_bot": true, "first_name": "Iris",
+            "username": "iris_test_bot"
+        }),
+        "getChat" => json!({
+            "id": chat.unwrap_or_default().parse::<i64>().unwrap_or_default(),
+            "type": "supergroup", "title": "Test", "is_forum": true
+        }),
+        "getChatMember" => json!({
+            "status": "administrator",
+            "user": { "id": BOT_ID, "is_bot": true, "first_name": "Iris" }
+        }),
+        _ => {
+            state.message_id += 1;
+            json!({ "message_id": state.message_id })
+        }
+    };
+    HttpResponse::Ok().json(json!({ "ok": true, "result": result }))
+}
+
+/// recorded like a bot api call with the topic as `chat_id`, the `x-`
+/// headers and the query in `body` and the pushed file as `file`
+#[route("/ntfy/{topic}", method = "POST", method = "PUT")]
+async fn ntfy(
+    rq: HttpRequest, topic: web::Path<String>, payload: web::Payload,
+) -> HttpResponse {
+    let data = payload.to_bytes().await.expect("mock body").to_vec();
+    let mut body = Map::new();
+    body.insert("chat_id".into(), Value::String(topic.into_inner()));
+    body.insert("query".into(), Value::String(rq.query_string().into()));
+    for (k, v) in rq.headers() {
+        if k.as_str().starts_with("x-") {
+            let v = v.to_str().expect("mock header").to_string();
+            body.insert(k.to_string(), Value::String(v));
+        }
+    }
+
+    let mut files = HashMap::new();
+    if rq.method() == actix_web::http::Method::PUT {
+        files.insert("file".into(), File { name: None, mime: None, data });
+    } else {
+        let text = String::from_utf8(data).expect("mock utf8 body");
+        body.insert("message".into(), Value::String(text));
+    }
+
+    let method = rq.method().to_string();
+    mock().state.lock().unwrap().calls.push(Call { method, body, files });
+    HttpResponse::Ok().json(json!({ "id": "mock" }))
+}
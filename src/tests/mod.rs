@@ -0,0 +1,169 @@
+use crate::config::Config;
+use crate::models::AppState;
+use actix_web::dev::{Service, ServiceResponse};
+use actix_web::{App, test, web::Data};
+use std::path::PathBuf;
+use std::sync::Once;
+
+mod abzar;
+mod admin;
+mod breaker;
+mod cli;
+mod config;
+mod email;
+mod health;
+mod logger;
+mod metrics;
+pub mod mock;
+mod proxy;
+mod server;
+
+/// the directory used as `local_dir` by the tests
+pub fn local_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("iris-test-{}", std::process::id()))
+}
+
+const CONFIG: &str = r#"
+tel_token = "TEST"
+tel_api = "{tel_api}"
+local_dir = "{local_dir}"
+trusted_peers = { users = ["{uid}"] }
+
+[breaker]
+failures = 2
+open_for = 60
+
+[smtp]
+host = "127.0.0.5"
+port = 1
+tls = "none"
+from = "iris@example.com"
+max_attachment = 10
+
+[channels]
+send = { chat = "-1001", thread = "11", pass = "pass" }
+no_thread = { chat = "-1002", pass = "pass" }
+parse_mode = { chat = "-1003", pass = "pass" }
+tel_error = { chat = "-1004", pass = "pass" }
+tel_429 = { chat = "-1005", pass = "pass" }
+send_mp = { chat = "-1006", thread = "16", pass = "pass" }
+send_file = { chat = "-1007", thread = "17", pass = "pass" }
+send_file_auth = { chat = "-1008", pass = "pass" }
+send_local = { chat = "-1009", pass = "pass" }
+argon2 = { chat = "-1010", pass = "{argon2}" }
+bcrypt = { chat = "-1011", pass = "{bcrypt}" }
+bearer = { chat = "-1012" }
+scoped = { chat = "-1013" }
+edit = { chat = "-1014" }
+signed = { chat = "-1015", pass = "pass", hmac_key = "key" }
+allow_ips = { chat = "-1016", pass = "pass", allow_ips = ["10.0.0.0/8", "::1"] }
+rate = { chat = "-1017", pass = "pass", limit = { rate = 0.01, burst = 2 } }
+quota = { chat = "-1018", pass = "pass", limit = { daily_bytes = 8 } }
+managed = { chat = "-1019" }
+audited = { chat = "-1020", pass = "pass" }
+redact = { chat = "-1021", pass = "pass", redact = { patterns = ['order-(?P<secret>\d+)'] } }
+redact_reject = { chat = "-1022", pass = "pass", redact = { detectors = ["jwt"], reject = true } }
+secret_file = { chat = "-1023", hmac_key = "file:{local_dir}/hmac-key" }
+token_file = { chat = "-1024" }
+cli = { chat = "-1025", pass = "pass" }
+peer = { chat = "-1026" }
+down = { ntfy = { url = "http://127.0.0.2:1", topic = "t" }, pass = "pass", fallback = "fallback" }
+down_alone = { ntfy = { url = "http://127.0.0.3:1", topic = "t" }, pass = "pass" }
+fallback = { chat = "-1027" }
+metrics = { chat = "-1028", pass = "pass" }
+mail_down = { email = ["ops@example.com"], pass = "pass", fallback = "mail_fallback" }
+mail_fallback = { chat = "-1029" }
+managed_limit = { chat = "-1031" }
+quota_shared = { chat = "-1032", limit = { daily_messages = 1 } }
+quota_other = { chat = "-1033" }
+allow_ips_sock = { chat = "-1030", pass = "pass", allow_ips = ["10.0.0.0/8"] }
+redact_down = { ntfy = { url = "http://127.0.0.6:1", topic = "t" }, pass = "pass", redact = { patterns = ['order-(?P<secret>\d+)'] }, fallback = "redact_fallback" }
+redact_fallback = { chat = "-1034" }
+push = { ntfy = { url = "{tel_api}/ntfy", topic = "alerts", title = "Iris", tags = ["warning", "café"] }, pass = "pass" }
+
+[tokens]
+test = { token = "token" }
+scoped = { token = "scoped", channels = ["scoped"], ops = ["send"] }
+admin = { token = "admin", ops = ["admin"] }
+from_file = { token = "file:{local_dir}/token", channels = ["token_file"] }
+hashed = { token = "{hashed_token}", prefix = "hashed_", channels = ["bearer"] }
+quota_token = { token = "quota_token", channels = ["quota_shared", "quota_other"], limit = { daily_messages = 2 } }
+
+[peers]
+me = { user = "{uid}", channels = ["peer"], ops = ["send"] }
+"#;
+
+/// start the mock bot api and point the config at it, safe to call often
+pub fn setup() {
+    static SETUP: Once = Once::new();
+    SETUP.call_once(|| {
+        let dir = local_dir();
+        std::fs::create_dir_all(&dir).expect("create local_dir");
+        std::fs::write(dir.join("token"), "from-file\n").unwrap();
+        std::fs::write(dir.join("hmac-key"), "file-key").unwrap();
+        let conf = CONFIG
+            .replace("{tel_api}", &mock::url())
+            .replace("{local_dir}", dir.to_str().unwrap())
+            // SAFETY: getuid can not fail
+            .replace("{uid}", &unsafe { libc::getuid() }.to_string())
+            .replace("{argon2}", &crate::auth::hash_pass("pass"))
+            .replace("{hashed_token}", &crate::auth::hash_pass("hashed_x"))
+            .replace("{bcrypt}", &bcrypt::hash("pass", 4).unwrap());
+        Config::set(&conf);
+    });
+}
+
+pub async fn app() -> impl Service<
+    actix_http::Request,
+    Response = ServiceResponse,
+    Error = actix_web::Error,
+> {
+    setup();
+    let state = state().await;
+    test::init_service(App::new().app_data(state).configure(crate::config_app))
+        .await
+}
+
+/// every app gets its own empty database
+pub async fn state() -> Data<AppState> {
+    let sql = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .expect("sqlite memory db");
+    crate::db::migrate(&sql).await.expect("migrate");
+
+    Data::new(AppState { sql })
+}
+
+/// a `multipart/form-data` body, returns the content type and the body
+pub fn multipart(
+    fields: &[(&str, &str)], file: Option<(&str, &str, &[u8])>,
+) -> (String, Vec<u8>) {
+    const BOUNDARY: &str = "iris-test-boundary";
+    let mut body = Vec::new();
+    for (name, value) in fields {
+        body.extend_from_slice(
+            format!(
+                "--{BOUNDARY}\r\nContent-Disposition: form-data; \
+                 name=\"{name}\"\r\n\r\n{value}\r\n"
+            )
+            .as_bytes(),
+        );
+    }
+    if let Some((name, fname, data)) = file {
+        body.extend_from_slice(
+            format!(
+                "--{BOUNDARY}\r\nContent-Disposition: form-data; \
+                 name=\"{name}\"; filename=\"{fname}\"\r\n\
+                 Content-Type: text/plain\r\n\r\n"
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(data);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{BOUNDARY}--\r\n").as_bytes());
+
+    (format!("multipart/form-data; boundary={BOUNDARY}"), body)
+}
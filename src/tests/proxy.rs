@@ -0,0 +1,40 @@
+use super::mock;
+use crate::config::Config;
+use crate::delivery::telegram;
+use crate::proxy;
+
+#[actix_web::test]
+async fn failover() {
+    // the mock takes absolute urls, so it can pass for an http proxy
+    let conf = Config::parse(&format!(
+        r#"
+        tel_token = "TEST"
+        tel_api = "{0}"
+        proxies = [
+            {{ name = "fo_dead", url = "http://127.0.0.1:1" }},
+            {{ name = "fo_mock", url = "{0}" }},
+        ]
+
+        [channels]
+        "#,
+        mock::url()
+    ))
+    .unwrap();
+
+    assert!(!proxy::is_down("fo_dead"));
+    let chat = telegram::get_chat(&conf, None, "-1201").await.unwrap();
+    assert_eq!(chat.kind, "supergroup");
+    assert!(proxy::is_down("fo_dead"));
+    assert!(!proxy::is_down("fo_mock"));
+
+    // a channel that only goes through the dead one still tries it
+    let via = ["fo_dead".to_string()];
+    assert!(telegram::get_chat(&conf, Some(&via), "-1201").await.is_err());
+    // and one with an empty list goes straight to telegram
+    telegram::get_chat(&conf, Some(&[]), "-1201").await.unwrap();
+    assert_eq!(mock::calls("-1201").len(), 2);
+
+    proxy::check(&conf, &conf.proxies[1]).await.unwrap();
+    let e = proxy::check(&conf, &conf.proxies[0]).await.unwrap_err();
+    assert!(!e.contains("botTEST"), "the token leaked: {e}");
+}
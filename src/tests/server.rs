@@ -0,0 +1,184 @@
+use super::mock;
+use crate::config::Config;
+use crate::server::{Bound, Listen, Server};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixListener;
+
+fn unix(path: &std::path::Path, mode: Option<u32>) -> Listen {
+    Listen {
+        tcp: None,
+        unix: Some(path.to_path_buf()),
+        mode,
+        owner: None,
+        group: None,
+    }
+}
+
+#[test]
+fn server_config() {
+    let base = "tel_token = \"T\"\n[channels]\n";
+    let conf = Config::parse(base).unwrap();
+    assert_eq!(conf.server, Server::default());
+
+    let conf = Config::parse(&format!(
+        "{base}[server]\nworkers = 2\nkeep_alive = 0\nlisten = [\
+         {{ tcp = \"127.0.0.1:7100\" }}, \
+         {{ unix = \"/run/iris.sock\", mode = 0o660, group = \"www-data\" }}]"
+    ))
+    .unwrap();
+    assert_eq!(conf.server.workers, Some(2));
+    assert_eq!(conf.server.keep_alive, Some(0));
+    assert_eq!(conf.server.listen[1].mode, Some(0o660));
+    assert_eq!(conf.server.url(), "http://127.0.0.1:7100");
+
+    // only workers given, the default listener stays
+    let conf = Config::parse(&format!("{base}[server]\nworkers = 1")).unwrap();
+    assert_eq!(conf.server.listen, Server::default().listen);
+
+    for (bad, why) in [
+        ("listen = []", "listen is empty"),
+        ("workers = 0", "at least 1"),
+        ("listen = [{ mode = 0o600 }]", "one of tcp or unix"),
+        ("listen = [{ tcp = \"127.0.0.1:1\", mode = 0o600 }]", "only for unix"),
+        ("listen = [{ unix = \"/s\", mode = 0o17777 }]", "bad mode"),
+        ("listen = [{ tcp = \"localhost\" }]", "invalid socket address"),
+    ] {
+        let Err(e) = Config::parse(&format!("{base}[server]\n{bad}")) else {
+            panic!("{bad} was accepted");
+        };
+        assert!(e.contains(why), "{e}");
+    }
+}
+
+#[test]
+fn stale_sockets() {
+    let dir = super::local_dir().join("sockets");
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // a crashed iris leaves its socket file behind
+    let path = dir.join("stale.sock");
+    drop(UnixListener::bind(&path).unwrap());
+    assert!(path.exists());
+
+    let server =
+        Server { listen: vec![unix(&path, Some(0o640))], ..Server::default() };
+    let bound = server.bind().unwrap();
+    assert!(matches!(bound[..], [Bound::Unix(_)]));
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o7777, 0o640);
+
+    // the socket is live now, it is not taken over
+    let e = server.bind().err().unwrap();
+    assert_eq!(e.kind(), std::io::ErrorKind::AddrInUse);
+    drop(bound);
+
+    let file = dir.join("file.sock");
+    std::fs::write(&file, "not a socket").unwrap();
+    let server = Server { listen: vec![unix(&file, None)], ..server };
+    let e = server.bind().err().unwrap();
+    assert!(e.to_string().contains("not a socket"), "{e}");
+    assert!(file.exists());
+}
+
+#[actix_web::test]
+async fn peer_credentials() {
+    use actix_web::{App, HttpServer};
+    use serde_json::{Value, json};
+
+    super::setup();
+    let path = super::local_dir().join("peer.sock");
+    let state = super::state().await;
+    let server = HttpServer::new(move || {
+        App::new().app_data(state.clone()).configure(crate::config_app)
+    })
+    .on_connect(crate::server::on_connect)
+    .workers(1)
+    .listen_uds(UnixListener::bind(&path).unwrap())
+    .unwrap();
+    actix_web::rt::spawn(server.run());
+
+    let client = reqwest::Client::builder().unix_socket(path).build().unwrap();
+    let send = |channel: &str| {
+        client
+            .post("http://localhost/api/abzar/send/")
+            .json(&json!({ "channel": channel, "text": "from a peer" }))
+    };
+
+    // no pass or token, the uid of this process is a peer
+    let rs = send("peer").send().await.unwrap();
+    assert_eq!(rs.status(), 200);
+    assert_eq!(mock::calls("-1026").len(), 1);
+
+    // forwarded by a proxy on the same socket
+    let rs = send("peer")
+        .header("x-forwarded-for", "203.0.113.9")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(rs.status(), 403);
+    let body: Value = rs.json().await.unwrap();
+    assert!(body["debug"].as_str().unwrap().contains("no token or pass"));
+
+    // this uid is in trusted_peers, so it may forward the client address
+    for (ip, status) in [("10.1.2.3", 200), ("192.168.1.1", 403)] {
+        let rs = client
+            .post("http://localhost/api/abzar/send/")
+            .header("x-forwarded-for", ip)
+            .json(
+                &json!({ "channel": "allow_ips_sock", "pass": "pass", "text": "x" }),
+            )
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(rs.status(), status, "{ip}");
+    }
+    assert_eq!(mock::calls("-1030").len(), 1);
+
+    // the peer acts like a token limited to its channels
+    let rs = send("no_thread").send().await.unwrap();
+    assert_eq!(rs.status(), 403);
+    let body: Value = rs.json().await.unwrap();
+    assert!(body["debug"].as_str().unwrap().contains("peer:me"));
+
+    let rs = client
+        .get("http://localhost/api/admin/audit/?channel=peer&order=asc")
+        .bearer_auth("admin")
+        .send()
+        .await
+        .unwrap();
+    let body: Value = rs.json().await.unwrap();
+    assert_eq!(body[0]["identity"], "peer:me");
+    assert_eq!(body[1]["identity"], "pass");
+}
+
+#[test]
+fn peer_config() {
+    use crate::server::PeerCred;
+
+    let conf = Config::parse(
+        "tel_token = \"T\"\n[channels]\na = { chat = \"-1\" }\n[peers]\n\
+         web = { group = \"33\" }\n\
+         backup = { user = \"1001\", channels = [\"a\"] }\n",
+    )
+    .unwrap();
+    let name = |uid, gid| conf.peer(PeerCred { uid, gid }).map(|v| v.name);
+    assert_eq!(name(1001, 1001).as_deref(), Some("backup"));
+    assert_eq!(name(1001, 33).as_deref(), Some("backup"));
+    assert_eq!(name(7, 33).as_deref(), Some("web"));
+    assert_eq!(name(7, 7), None);
+
+    for (bad, why) in [
+        ("x = { channels = [\"a\"] }", "one of user or group"),
+        ("x = { user = \"1\", group = \"1\" }", "one of user or group"),
+        ("x = { user = \"no-such-user-here\" }", "no user"),
+        ("x = { group = \"1\", channels = [\"b\"] }", "unknown channel b"),
+    ] {
+        let Err(e) = Config::parse(&format!(
+            "tel_token = \"T\"\n[channels]\na = {{ chat = \"-1\" }}\n\
+             [peers]\n{bad}"
+        )) else {
+            panic!("{bad} was accepted");
+        };
+        assert!(e.contains(why), "{e}");
+    }
+}